@@ -101,12 +101,7 @@ impl Player {
 
 			ctx.send_packet(Packet::UpdateInstance {
 				id: instance_id,
-				delta: UpdateInstance {
-					position: pos,
-					rotation: rot,
-					scale: None,
-					body: None,
-				},
+				delta: UpdateInstance::transform(pos, rot),
 			});
 
 			self.ticks_since_last_update = 0;
@@ -2,6 +2,7 @@
 
 use std::{
 	f32::consts::FRAC_PI_4,
+	io,
 	time::{Duration, Instant},
 };
 
@@ -10,6 +11,8 @@ use ira::{
 	glam::{Quat, Vec3},
 	packet::{Packet, TrustedPacket},
 	physics::InstanceHandle,
+	server::DisconnectReason,
+	transport::{Listener, TcpTransport, Transport},
 	ColliderBuilder, Context, Game, Instance, KeyCode, RigidBodyBuilder,
 };
 use ira_drum::Drum;
@@ -29,17 +32,19 @@ enum Message {
 }
 
 impl ira::App<Message> for App {
-	fn listen() -> std::net::TcpListener {
-		std::net::TcpListener::bind("0.0.0.0:10585").expect("failed to bind to port 10585")
+	fn listen() -> io::Result<Box<dyn Listener>> {
+		Ok(Box::new(std::net::TcpListener::bind("0.0.0.0:10585")?))
 	}
 
-	fn connect() -> std::net::TcpStream {
-		std::net::TcpStream::connect(
-			std::env::args()
-				.nth(1)
-				.expect("expected an IP address to connect to as the first argument"),
-		)
-		.expect("failed to connect to server")
+	fn connect() -> io::Result<Box<dyn Transport>> {
+		let addr = std::env::args().nth(1).ok_or_else(|| {
+			io::Error::new(
+				io::ErrorKind::InvalidInput,
+				"expected an IP address to connect to as the first argument",
+			)
+		})?;
+
+		Ok(Box::new(TcpTransport::connect(addr)?))
 	}
 
 	fn create_player(ctx: &mut Context<Message>) -> (u32, ira::InstanceBuilder) {
@@ -76,6 +81,14 @@ impl ira::App<Message> for App {
 		}
 	}
 
+	fn on_disconnect(
+		ctx: &mut Context<Message>,
+		client_id: ira::client::ClientId,
+		reason: &DisconnectReason,
+	) {
+		println!("{client_id:?} disconnected: {reason}");
+	}
+
 	fn on_ready(ctx: &mut Context<Message>) -> Self {
 		let car_id = ctx.drum.model_id("bottled_car").unwrap();
 
@@ -145,7 +158,6 @@ impl ira::App<Message> for App {
 					.rigidbody(RigidBodyBuilder::dynamic()),
 			);
 
-			#[cfg(feature = "server")]
 			self.cars.push(handle);
 		}
 
@@ -153,6 +165,15 @@ impl ira::App<Message> for App {
 		if ctx.pressed(ira::KeyCode::KeyH) && self.hello.check() {
 			ctx.send_packet(Packet::new(Message::Hello));
 		}
+
+		#[cfg(feature = "client")]
+		if ctx.just_pressed(ira::KeyCode::KeyR) {
+			if ctx.client_id.is_some() {
+				ctx.disconnect();
+			} else {
+				ctx.reconnect::<Self>();
+			}
+		}
 	}
 }
 
@@ -1,15 +1,11 @@
-use std::{
-	io::{self, Read},
-	mem,
-	net::TcpStream,
-	sync::atomic::{AtomicU32, Ordering},
-};
+use std::{io, time::Instant};
 
 use tracing::warn;
 
 use crate::{
 	packet::{self, Packet},
-	server::{InstanceId, Owners},
+	server::{NetworkConfig, Owners},
+	transport::{Delivery, Transport},
 };
 
 #[derive(
@@ -26,142 +22,154 @@ impl ClientId {
 	}
 }
 
-pub struct PartialPacket {
-	pub(crate) len: Option<usize>,
-	pub(crate) data: Vec<u8>,
-	pub(crate) offset: usize,
+/// A token bucket limiting the rate at which packets are read from a client.
+struct Budget {
+	tokens: f32,
+	rate: f32,
+	burst: f32,
+	last: Instant,
 }
 
-impl Default for PartialPacket {
-	fn default() -> Self {
-		Self {
-			len: None,
-			data: vec![0; 4],
-			offset: 0,
+impl Budget {
+	/// Takes a token from the bucket, returning `false` if it is empty.
+	fn take(&mut self) -> bool {
+		let now = Instant::now();
+
+		self.tokens =
+			(self.tokens + now.duration_since(self.last).as_secs_f32() * self.rate).min(self.burst);
+		self.last = now;
+
+		if self.tokens < 1.0 {
+			return false;
 		}
+
+		self.tokens -= 1.0;
+		true
 	}
 }
 
 pub struct Client {
-	pub(crate) stream: TcpStream,
+	pub(crate) transport: Box<dyn Transport>,
 	pub(crate) id: ClientId,
-
-	/// A partial packet that is being read.
-	///
-	/// When reading the next packet, check if this is `Some`. If it is,
-	/// read the remaining data into the partial packet.
-	pub(crate) next_packet: PartialPacket,
+	budget: Option<Budget>,
+	/// A frame received while the client was rate limited, which is read again first.
+	limited: Option<Vec<u8>>,
 }
 
 impl Client {
-	/// Creates a new client from a stream.
-	///
-	/// # Panics
-	///
-	/// Panics if the stream cannot be cloned.
+	/// Creates a new client from a transport.
 	#[must_use]
-	pub fn new(stream: TcpStream, id: ClientId) -> Self {
+	pub fn new(transport: Box<dyn Transport>, id: ClientId) -> Self {
 		Self {
-			stream,
+			transport,
 			id,
-			next_packet: PartialPacket::default(),
+			budget: None,
+			limited: None,
 		}
 	}
 
+	/// Applies the maximum packet size and receive rate of `config` to the client.
+	pub fn limit(&mut self, config: &NetworkConfig) {
+		self.transport.set_max_frame_size(config.max_packet_size);
+		self.budget = Some(Budget {
+			tokens: config.packet_burst,
+			rate: config.max_packets_per_second,
+			burst: config.packet_burst,
+			last: Instant::now(),
+		});
+	}
+
 	#[must_use]
 	pub fn id(&self) -> ClientId {
 		self.id
 	}
 
-	/// Tries to complete the current packet, returning
-	/// the packet data if it completes.
+	/// Tries to receive the next packet, returning its data.
 	///
 	/// # Errors
 	///
-	/// See [`TcpStream::read`].
+	/// See [`Transport::recv`].
 	pub fn next_packet(&mut self) -> io::Result<Vec<u8>> {
-		let mut partial = mem::take(&mut self.next_packet);
-
-		let len = if let Some(len) = partial.len {
-			len
-		} else {
-			while partial.offset < 4 {
-				let n = match self.stream.read(&mut partial.data[partial.offset..4]) {
-					Ok(n) => n,
-					Err(e) => {
-						self.next_packet = partial;
-						return Err(e);
-					}
-				};
-
-				partial.offset += n;
-			}
-
-			let len = u32::from_le_bytes([
-				partial.data[0],
-				partial.data[1],
-				partial.data[2],
-				partial.data[3],
-			]) as usize;
-
-			partial.len = Some(len);
-			partial.data.resize(len, 0);
-			partial.offset = 0;
-
-			len
-		};
-
-		while partial.offset < len {
-			let n = match self.stream.read(&mut partial.data[partial.offset..len]) {
-				Ok(n) => n,
-				Err(e) => {
-					self.next_packet = partial;
-					return Err(e);
-				}
-			};
+		self.transport.recv()
+	}
 
-			partial.offset += n;
+	/// Sends the data of a packet.
+	///
+	/// # Errors
+	///
+	/// See [`Transport::send`] and [`Transport::send_unreliable`].
+	pub fn send(&mut self, data: &[u8], delivery: Delivery) -> io::Result<()> {
+		match delivery {
+			Delivery::Reliable => self.transport.send(data),
+			Delivery::Unreliable => self.transport.send_unreliable(data),
 		}
-
-		Ok(partial.data)
 	}
 
 	/// Tries to read a packet from the client.
 	///
+	/// Returns `Ok(None)` if the packet was dropped, such as when the client tries to
+	/// change an instance it doesn't own. More packets may still be waiting after it.
+	///
 	/// # Errors
 	///
-	/// See [`Packet::read`].
+	/// Returns [`packet::Error::RateLimited`] if the client has sent more packets than
+	/// allowed by [`Client::limit`]. Otherwise, see [`Packet::read`].
 	pub fn try_read_packet<M>(
 		&mut self,
-		next_instance_id: &AtomicU32,
-		owners: &mut Owners,
+		owners: &Owners,
 	) -> Result<Option<Packet<M>>, packet::Error>
 	where
 		M: bitcode::DecodeOwned + bitcode::Encode,
 	{
-		let packet = Packet::read(self)?;
+		let data = match self.limited.take() {
+			Some(data) => data,
+			None => self.next_packet()?,
+		};
 
-		match packet {
-			Packet::CreateInstance { .. } => {
-				let id = InstanceId::new(next_instance_id.fetch_add(1, Ordering::SeqCst));
-				owners.insert(id, self.id);
-			}
-			Packet::DeleteInstance { id } => {
-				if owners.get(&id) != Some(&self.id) {
-					return Ok(None);
-				}
+		// only frames that have been received count against the budget
+		if !self.budget.as_mut().is_none_or(Budget::take) {
+			self.limited = Some(data);
 
-				owners.remove(&id);
-			}
-			Packet::UpdateInstance { id, .. } => {
+			return Err(packet::Error::RateLimited);
+		}
+
+		let packet = bitcode::decode(&data)?;
+
+		match packet {
+			Packet::DeleteInstance { id }
+			| Packet::UpdateInstance { id, .. }
+			| Packet::SetProperty { id, .. } => {
 				if owners.get(&id) != Some(&self.id) {
 					return Ok(None);
 				}
 			}
-			Packet::DeleteClient => {
-				owners.retain(|_, owner| *owner != self.id);
+			// the server takes over its instances once the client is dropped
+			Packet::CreateInstance { .. }
+			| Packet::CreateClient { .. }
+			| Packet::DeleteClient { .. }
+			| Packet::Custom(..)
+			| Packet::Message { .. }
+			| Packet::Snapshot { .. }
+			| Packet::AckSnapshot { .. }
+			| Packet::Request { .. }
+			| Packet::Response { .. }
+			| Packet::Input { .. }
+			| Packet::InputAck { .. }
+			| Packet::JoinRoom { .. }
+			| Packet::SetRoomState { .. }
+			| Packet::Room { .. }
+			| Packet::SetOwner { .. }
+			| Packet::Spawned { .. }
+			| Packet::RequestOwnership { .. }
+			| Packet::CreateJoint { .. }
+			| Packet::DeleteJoint { .. }
+			| Packet::UpdateJoint { .. }
+			| Packet::Ping { .. }
+			| Packet::Pong { .. } => {}
+			Packet::SetGravity { .. } => {
+				warn!(client_id = ?self.id, "client tried to change gravity");
+				return Ok(None);
 			}
-			Packet::CreateClient { .. } | Packet::Custom(..) => {}
 			Packet::Connected { .. } => {
 				warn!("received Connected packet from self");
 				return Ok(None);
@@ -172,18 +180,43 @@ impl Client {
 	}
 }
 
-impl io::Write for Client {
-	fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-		self.stream.write(buf)
-	}
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::transport::channel;
 
-	fn flush(&mut self) -> io::Result<()> {
-		self.stream.flush()
+	fn read(client: &mut Client) -> Result<Option<Packet<()>>, packet::Error> {
+		client.try_read_packet(&Owners::default())
 	}
-}
 
-impl io::Read for Client {
-	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-		self.stream.read(buf)
+	#[test]
+	fn empty_reads_do_not_count_against_the_budget() {
+		let (local, remote) = channel::pair();
+		let mut client = Client::new(Box::new(local), ClientId::SERVER.next());
+		let mut sender = Client::new(Box::new(remote), ClientId::SERVER);
+
+		client.limit(&NetworkConfig {
+			max_packets_per_second: 0.0,
+			packet_burst: 1.0,
+			..NetworkConfig::default()
+		});
+
+		for _ in 0..10 {
+			assert!(matches!(
+				read(&mut client),
+				Err(packet::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock
+			));
+		}
+
+		let ping = bitcode::encode(&Packet::<()>::Ping {
+			sequence: 0,
+			time: 0,
+		});
+
+		sender.send(&ping, Delivery::Reliable).unwrap();
+		sender.send(&ping, Delivery::Reliable).unwrap();
+
+		assert!(matches!(read(&mut client), Ok(Some(Packet::Ping { .. }))));
+		assert!(matches!(read(&mut client), Err(packet::Error::RateLimited)));
 	}
 }
@@ -1,8 +1,21 @@
 use crate::{
 	client::ClientId,
-	packet::{CreateInstance, Packet, TrustedPacket},
-	physics::{InstanceHandle, PhysicsState},
-	server::{self, InstanceId},
+	handshake::Handshake,
+	interpolation::Interpolation,
+	joint::Joint,
+	layer::Layers,
+	packet::{CreateInstance, Packet, TrustedPacket, UpdateInstance},
+	physics::{InstanceHandle, PhysicsConfig, PhysicsState},
+	prediction::Prediction,
+	property::Properties,
+	rpc::{PendingRequest, RequestId},
+	server::{
+		self,
+		heartbeat::{NetworkStats, Shared},
+		room::{RoomId, RoomState},
+		DisconnectReason, InstanceId, JointId, NetworkConfig,
+	},
+	transport::{Listener, TcpTransport, Transport},
 	DrumExt, GpuDrum, Instance, InstanceBuilder,
 };
 
@@ -11,15 +24,16 @@ use crate::render::RenderState;
 
 use std::{
 	collections::BTreeMap,
-	fmt,
+	fmt, io,
 	sync::{atomic::AtomicU32, mpsc, Arc},
+	thread,
 	time::{self, Duration},
 };
 
-use glam::Vec2;
+use glam::{Vec2, Vec3};
 use ira_drum::Drum;
-use rapier3d::data::Arena;
-use tracing::info;
+use rapier3d::{data::Arena, dynamics::RigidBodyHandle};
+use tracing::{error, info};
 #[cfg(feature = "client")]
 use winit::{
 	application::ApplicationHandler,
@@ -36,24 +50,192 @@ use winit::{
 /// For networked games, implement the [`Network`] trait as well.
 #[allow(unused_variables)]
 pub trait App<M = ()> {
-	/// Connects to the server. This is only called on clients.
+	/// The input of the local player for a single fixed update, sent to the server
+	/// when prediction is enabled with [`Context::predict`].
+	type Input: bitcode::Encode + bitcode::DecodeOwned = ();
+
+	/// The version of the messages, inputs and other types the application sends over
+	/// the network, which must match between the client and server for a connection
+	/// to be accepted. Bump this whenever one of them changes incompatibly.
+	const PROTOCOL_VERSION: u32 = 0;
+
+	/// Returns the id of the application, which must match between the client
+	/// and server for a connection to be accepted.
+	///
+	/// By default, this is the name of the type implementing [`App`].
 	#[must_use]
-	fn connect() -> std::net::TcpStream {
-		std::net::TcpStream::connect("127.0.0.1:12345").unwrap()
+	fn app_id() -> &'static str {
+		std::any::type_name::<Self>()
 	}
 
-	/// Creates a new listener for the server to receive connections.
+	/// Returns the limits applied to connections, such as the maximum packet size
+	/// and the rate at which clients can send packets.
 	#[must_use]
-	fn listen() -> std::net::TcpListener {
-		std::net::TcpListener::bind("127.0.0.1:12345").unwrap()
+	fn network() -> NetworkConfig {
+		NetworkConfig::default()
+	}
+
+	/// Returns the credentials sent to the server when connecting, such as a
+	/// session token. This is only called on clients.
+	///
+	/// By default, no credentials are sent.
+	#[must_use]
+	fn credentials() -> Vec<u8> {
+		Vec::new()
+	}
+
+	/// Called on the server when a client connects, with the credentials it sent
+	/// (see [`App::credentials`]). This is called on the server thread, before the
+	/// client is given a player instance.
+	///
+	/// Returning `Ok` accepts the client, optionally attaching an identity (such as
+	/// a user id) to its [`ClientId`] that can be retrieved with [`Context::identity`].
+	/// Returning `Err` rejects the client with the given reason.
+	///
+	/// By default, every client is accepted without an identity.
+	///
+	/// # Errors
+	///
+	/// Returns the reason the client was rejected.
+	fn authenticate(client_id: ClientId, credentials: &[u8]) -> Result<Option<String>, String> {
+		Ok(None)
+	}
+
+	/// Called on the server for every packet received from a client, before it is
+	/// applied or forwarded to anyone. `instances` contains the instances created so
+	/// far, before the packet is applied.
+	///
+	/// Packets with collider shapes or model ids that cannot be built are rejected
+	/// before this is called. Ownership is checked afterwards, so an accepted packet
+	/// may still be dropped if it changes an instance or joint the client does not own.
+	///
+	/// Returning `Ok` accepts the packet, including any changes made to it, such as
+	/// clamping the velocity in an [`UpdateInstance`](crate::packet::UpdateInstance).
+	/// Returning `Err` drops it, and counts it against the client (see
+	/// [`Context::rejected_packets`] and [`NetworkConfig::max_rejected_packets`]).
+	///
+	/// This is called on the server thread. By default, every packet is accepted.
+	///
+	/// # Errors
+	///
+	/// Returns the reason the packet was rejected.
+	fn validate_packet(
+		client_id: ClientId,
+		packet: &mut Packet<M>,
+		instances: &BTreeMap<InstanceId, CreateInstance>,
+	) -> Result<(), String> {
+		Ok(())
+	}
+
+	/// Called on the server to decide whether an instance is relevant to a client, when
+	/// [`NetworkConfig::interest`] is [`Interest::Custom`](crate::server::interest::Interest::Custom).
+	/// `player` is the position of the client's player instance.
+	///
+	/// This is called on the server thread. By default, every instance is relevant.
+	#[must_use]
+	fn is_relevant(
+		client_id: ClientId,
+		player: Vec3,
+		id: InstanceId,
+		instance: &CreateInstance,
+	) -> bool {
+		true
+	}
+
+	/// Called on the server when a client asks for the ownership of an instance to be
+	/// given to `owner` with [`Context::request_ownership`], such as to pick up a ball.
+	/// Returning `true` transfers it, and everyone that can see the instance receives
+	/// a [`Packet::SetOwner`].
+	///
+	/// By default, only the current owner of an instance can give it away.
+	fn approve_ownership(
+		ctx: &mut Context<M>,
+		client_id: ClientId,
+		id: InstanceId,
+		owner: ClientId,
+	) -> bool {
+		ctx.owner(id) == client_id
+	}
+
+	/// Connects to the server. This is only called on clients.
+	///
+	/// By default, this connects over TCP to `127.0.0.1:12345`.
+	/// See [`transport`](crate::transport) for other transports.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the server could not be reached, which is passed to
+	/// [`App::on_network_error`].
+	fn connect() -> io::Result<Box<dyn Transport>> {
+		Ok(Box::new(TcpTransport::connect("127.0.0.1:12345")?))
+	}
+
+	/// Creates a new listener for the server to receive connections.
+	///
+	/// By default, this listens over TCP on `127.0.0.1:12345`.
+	/// See [`transport`](crate::transport) for other transports.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the server could not listen for connections, which stops
+	/// the server and is passed to [`App::on_network_error`].
+	fn listen() -> io::Result<Box<dyn Listener>> {
+		Ok(Box::new(std::net::TcpListener::bind("127.0.0.1:12345")?))
 	}
 
 	/// Called when a remote player joins the game. This should return
 	/// the model id, and a builder for the instance.
 	fn create_player(ctx: &mut Context<M>) -> (u32, InstanceBuilder);
 
+	/// Returns the names of the collision layers used by the game, which are
+	/// declared on [`Context::layers`] before anything else is created.
+	///
+	/// See [`Layers`] for more information.
+	#[must_use]
+	fn layers() -> &'static [&'static str] {
+		&[]
+	}
+
+	/// Returns the configuration of the physics world, which is applied before
+	/// anything else is created.
+	#[must_use]
+	fn physics() -> PhysicsConfig {
+		PhysicsConfig::default()
+	}
+
 	/// Called when a packet is received from the server.
+	///
+	/// Requests and responses are handled by [`App::on_request`] and the callbacks
+	/// given to [`Context::request`] instead, and inputs by [`App::simulate`].
 	fn on_packet(ctx: &mut Context<M>, packet: TrustedPacket<M>) {}
+	/// Called when a request made with [`Context::request`] is received. Returning
+	/// `Some` responds right away, otherwise [`Context::respond`] can be used later
+	/// with the same `id`. Requests without a response time out on the sender.
+	///
+	/// On the server, `client_id` is the client that made the request. On a client,
+	/// it is always [`ClientId::SERVER`]. Requests are not passed to [`App::on_packet`].
+	fn on_request(
+		ctx: &mut Context<M>,
+		client_id: ClientId,
+		id: RequestId,
+		message: M,
+	) -> Option<M> {
+		None
+	}
+	/// Called when a client leaves the game, after its player instance has been removed.
+	///
+	/// On a client, this is also called with [`ClientId::SERVER`] when the connection
+	/// to the server ends, after everything received from the server has been removed.
+	/// The [`Context`] can still be used locally, such as to show a menu, and
+	/// [`Context::reconnect`] can be used to join again.
+	fn on_disconnect(ctx: &mut Context<M>, client_id: ClientId, reason: &DisconnectReason) {}
+	/// Called on a client when the connection to the server fails, such as when the
+	/// server is unreachable, rejects the connection or stops responding, and on the
+	/// server when it cannot listen for connections. This is called right before
+	/// [`App::on_disconnect`].
+	///
+	/// By default, the error is only logged.
+	fn on_network_error(ctx: &mut Context<M>, error: &server::Error) {}
 	/// Called once at the start of the program, right after the window
 	/// is created but before anything else is done.
 	fn on_init() -> Drum;
@@ -62,9 +244,21 @@ pub trait App<M = ()> {
 	/// Called once per frame, right before rendering. Note that this
 	/// will not be called if the `render` feature is not enabled.
 	fn on_update(&mut self, ctx: &mut Context<M>, delta: Duration) {}
-	/// Called every 1/60th of a second. If queued at the same time as an update,
+	/// Called once per physics step, which is every 1/60th of a second by default
+	/// (see [`PhysicsConfig::timestep`]). If queued at the same time as an update,
 	/// this will always be called first.
 	fn on_fixed_update(&mut self, ctx: &mut Context<M>) {}
+	/// Returns the local player's input for the next fixed update, if prediction is
+	/// enabled with [`Context::predict`]. This is called right before the physics step,
+	/// and returning `None` skips the tick.
+	fn input(&mut self, ctx: &mut Context<M>) -> Option<Self::Input> {
+		None
+	}
+	/// Applies a single fixed update of input to a player's body, such as by setting
+	/// its velocity. This is called on clients to predict their own player, and on the
+	/// server with the inputs received from each client, so both must produce the
+	/// same result.
+	fn simulate(ctx: &mut Context<M>, body: RigidBodyHandle, input: &Self::Input) {}
 }
 
 /// A game instance.
@@ -128,7 +322,7 @@ impl<A: App<M>, M> Game<A, M> {
 	where
 		M: bitcode::Encode + bitcode::DecodeOwned + fmt::Debug + Send + 'static,
 	{
-		use tracing::info;
+		use tracing::{error, info};
 
 		let drum = A::on_init();
 		let mut ctx = pollster::block_on(Context::<M>::new::<A>(drum));
@@ -161,8 +355,25 @@ pub struct Context<M = ()> {
 	pub(crate) handles: BTreeMap<InstanceId, InstanceHandle>,
 	pub(crate) instance_ids: BTreeMap<InstanceHandle, InstanceId>,
 	pub(crate) clients: BTreeMap<ClientId, InstanceId>,
+	pub(crate) identities: BTreeMap<ClientId, String>,
+	pub(crate) joints: BTreeMap<JointId, Joint>,
+	pub(crate) requests: BTreeMap<RequestId, PendingRequest<M>>,
+	pub(crate) interpolation: Interpolation,
+	pub(crate) prediction: Prediction,
+	pub(crate) network: NetworkConfig,
+	pub(crate) next_request_id: u32,
+	pub(crate) owners: BTreeMap<InstanceId, ClientId>,
+	pub(crate) properties: BTreeMap<InstanceId, Properties>,
+	/// The instances created by this client that the server has not assigned an id to
+	/// yet, keyed by the id they were sent with.
+	pub(crate) spawning: BTreeMap<InstanceId, InstanceHandle>,
+	pub(crate) rooms: BTreeMap<ClientId, RoomId>,
+	pub(crate) room_states: BTreeMap<RoomId, RoomState>,
+	pub(crate) next_room_id: u32,
 
 	pub instances: Arena<Instance>,
+	/// The named collision layers, declared with [`App::layers`].
+	pub layers: Layers,
 
 	/// Used to send messages to the thread that communicates with the server.
 	/// If the "server" feature is enabled, this will be directly to the server.
@@ -170,8 +381,17 @@ pub struct Context<M = ()> {
 	/// Used to receive messages from the thread that communicates with the server.
 	/// If the "server" feature is enabled, this will be directly from the server.
 	pub(crate) packet_rx: mpsc::Receiver<TrustedPacket<M>>,
+	/// The thread that runs the server, or communicates with the remote server.
+	pub(crate) server: Option<thread::JoinHandle<Result<(), server::Error>>>,
+	/// The error that stopped the server thread, if any.
+	pub(crate) network_error: Option<server::Error>,
+	/// Latency and clock state measured by the server thread.
+	pub(crate) shared: Arc<Shared>,
+	/// The handshake sent to the server, kept to reconnect.
+	pub(crate) handshake: Handshake,
 
 	pub(crate) next_instance_id: Arc<AtomicU32>,
+	pub(crate) next_joint_id: Arc<AtomicU32>,
 	pub client_id: Option<ClientId>,
 	pub instance_id: Option<InstanceId>,
 
@@ -187,13 +407,12 @@ impl<M> Context<M> {
 	{
 		#[cfg(feature = "client")]
 		let render = RenderState::new(window, &drum).await;
-		let physics = PhysicsState::default();
+		let physics = PhysicsState::new(&A::physics());
 
 		let (server_packet_tx, packet_rx) = mpsc::channel();
 		let (packet_tx, server_packet_rx) = mpsc::channel();
 
-		let next_instance_id = Arc::new(AtomicU32::new(0));
-
+		let handshake = Handshake::new::<A, M>(&drum);
 		let drum = drum.into_gpu(
 			#[cfg(feature = "client")]
 			&render.device,
@@ -221,32 +440,245 @@ impl<M> Context<M> {
 			handles: BTreeMap::new(),
 			instance_ids: BTreeMap::new(),
 			instances: Arena::new(),
+			layers: Layers::default(),
 			clients: BTreeMap::new(),
+			identities: BTreeMap::new(),
+			joints: BTreeMap::new(),
+			requests: BTreeMap::new(),
+			interpolation: Interpolation::default(),
+			prediction: Prediction::default(),
+			network: A::network(),
+			next_request_id: 0,
+			owners: BTreeMap::new(),
+			properties: BTreeMap::new(),
+			spawning: BTreeMap::new(),
+			rooms: BTreeMap::new(),
+			room_states: BTreeMap::new(),
+			next_room_id: 0,
 
 			packet_tx,
 			packet_rx,
+			server: None,
+			network_error: None,
+			shared: Arc::new(Shared::default()),
+			handshake: handshake.clone(),
 
-			next_instance_id: Arc::clone(&next_instance_id),
+			next_instance_id: Arc::new(AtomicU32::new(0)),
+			next_joint_id: Arc::new(AtomicU32::new(0)),
 			client_id: None,
 			instance_id: None,
 		};
 
-		std::thread::spawn({
-			let (model_id, builder) = A::create_player(&mut ctx);
+		for layer in A::layers() {
+			ctx.layers.register(layer);
+		}
 
-			move || {
-				server::run::<A, _>(
-					server_packet_tx,
-					server_packet_rx,
-					next_instance_id,
-					CreateInstance::from_builder(&builder, model_id),
-				);
-			}
-		});
+		ctx.spawn_server::<A>(handshake, server_packet_tx, server_packet_rx);
 
 		ctx
 	}
 
+	/// Spawns the thread that runs the server, or communicates with the remote server.
+	fn spawn_server<A: App<M>>(
+		&mut self,
+		handshake: Handshake,
+		packet_tx: mpsc::Sender<TrustedPacket<M>>,
+		packet_rx: mpsc::Receiver<Packet<M>>,
+	) where
+		M: bitcode::Encode + bitcode::DecodeOwned + fmt::Debug + Send + 'static,
+	{
+		let (model_id, builder) = A::create_player(self);
+		let local = CreateInstance::from_builder(&builder, model_id);
+
+		let next_instance_id = Arc::clone(&self.next_instance_id);
+		let next_joint_id = Arc::clone(&self.next_joint_id);
+		let shared = Arc::clone(&self.shared);
+		let network = self.network;
+		let models = self.drum.models.len();
+
+		self.server = Some(thread::spawn(move || {
+			server::run::<A, _>(
+				packet_tx,
+				packet_rx,
+				next_instance_id,
+				next_joint_id,
+				handshake,
+				models,
+				network,
+				shared,
+				local,
+			)
+		}));
+	}
+
+	/// Connects to the server again, after the previous connection has ended.
+	///
+	/// Does nothing if the client is still connected (or connecting), or on the server
+	/// while it is running.
+	pub fn reconnect<A: App<M>>(&mut self)
+	where
+		M: bitcode::Encode + bitcode::DecodeOwned + fmt::Debug + Send + 'static,
+	{
+		if self.server.is_some() {
+			return;
+		}
+
+		let (server_packet_tx, packet_rx) = mpsc::channel();
+		let (packet_tx, server_packet_rx) = mpsc::channel();
+
+		self.packet_tx = packet_tx;
+		self.packet_rx = packet_rx;
+		self.network_error = None;
+		self.shared = Arc::new(Shared::default());
+
+		self.spawn_server::<A>(self.handshake.clone(), server_packet_tx, server_packet_rx);
+	}
+
+	/// Leaves the server. [`App::on_disconnect`] is called with [`ClientId::SERVER`]
+	/// once the connection has been closed.
+	///
+	/// This does nothing on the server.
+	pub fn disconnect(&self) {
+		#[cfg(not(feature = "server"))]
+		let _ = self.packet_tx.send(Packet::DeleteClient {
+			reason: DisconnectReason::Left,
+		});
+	}
+
+	/// Removes everything received from the server, once the connection has ended.
+	fn clear_network_state(&mut self) {
+		let instances = self.handles.keys().copied().collect::<Vec<_>>();
+		let joints = self.joints.keys().copied().collect::<Vec<_>>();
+
+		for id in joints {
+			self.remove_joint_local(id);
+		}
+
+		for id in instances {
+			self.remove_instance_local(id);
+		}
+
+		self.fail_requests(None);
+		self.prediction.reset();
+		self.clients.clear();
+		self.identities.clear();
+		self.owners.clear();
+		self.properties.clear();
+		self.spawning.clear();
+		self.rooms.clear();
+		self.room_states.clear();
+		self.shared.stats.lock().unwrap().clear();
+		self.shared.rejected.lock().unwrap().clear();
+		self.client_id = None;
+		self.instance_id = None;
+	}
+
+	/// Returns the identity attached to a client by [`App::authenticate`], if any.
+	///
+	/// Identities are only known by the server.
+	#[must_use]
+	pub fn identity(&self, client_id: ClientId) -> Option<&str> {
+		self.identities.get(&client_id).map(String::as_str)
+	}
+
+	/// Returns the latency of the connection to a client, measured with heartbeats.
+	///
+	/// On the server, this is available for every remote client. On a client, only
+	/// the connection to the server is measured, under [`ClientId::SERVER`].
+	/// Returns `None` until the first heartbeat has been answered.
+	///
+	/// # Panics
+	///
+	/// Panics if the server thread panicked while holding the stats lock.
+	#[must_use]
+	pub fn network_stats(&self, client_id: ClientId) -> Option<NetworkStats> {
+		self.shared.stats.lock().unwrap().get(&client_id).copied()
+	}
+
+	/// Returns the number of packets from a client that have been rejected by
+	/// [`App::validate_packet`]. This is only known by the server.
+	///
+	/// # Panics
+	///
+	/// Panics if the server thread panicked while holding the lock.
+	#[must_use]
+	pub fn rejected_packets(&self, client_id: ClientId) -> u32 {
+		self.shared
+			.rejected
+			.lock()
+			.unwrap()
+			.get(&client_id)
+			.copied()
+			.unwrap_or_default()
+	}
+
+	/// Returns the time elapsed since the server started.
+	///
+	/// On a client, this is estimated from the server's heartbeats, and is
+	/// the same across all connected clients up to their clock error.
+	#[must_use]
+	pub fn server_time(&self) -> Duration {
+		self.shared.clock.now()
+	}
+
+	/// Returns the number of fixed updates the server has run since it started,
+	/// derived from [`Self::server_time`] and the physics timestep.
+	#[must_use]
+	pub fn server_tick(&self) -> u64 {
+		(self.server_time().as_secs_f64() / f64::from(self.physics.integration.dt)) as u64
+	}
+
+	/// Returns the error that stopped the connection to the server, if any,
+	/// such as the server rejecting the connection.
+	#[must_use]
+	pub fn network_error(&self) -> Option<&server::Error> {
+		self.network_error.as_ref()
+	}
+
+	/// Checks whether the server thread has stopped, storing its error.
+	fn poll_server<A: App<M>>(&mut self) {
+		if !self
+			.server
+			.as_ref()
+			.is_some_and(thread::JoinHandle::is_finished)
+		{
+			return;
+		}
+
+		let Some(server) = self.server.take() else {
+			return;
+		};
+
+		let reason = match server.join() {
+			Ok(Ok(())) => DisconnectReason::Left,
+			Ok(Err(e)) => {
+				error!(error = %e, "connection to server stopped");
+
+				A::on_network_error(self, &e);
+
+				let reason = DisconnectReason::from(&e);
+
+				self.network_error = Some(e);
+
+				reason
+			}
+			Err(..) => {
+				error!("server thread panicked");
+
+				DisconnectReason::Error("server thread panicked".to_string())
+			}
+		};
+
+		// anything still queued was sent before the connection ended
+		while let Ok(packet) = self.packet_rx.try_recv() {
+			Self::on_packet::<A>(self, packet);
+		}
+
+		self.clear_network_state();
+
+		A::on_disconnect(self, ClientId::SERVER, &reason);
+	}
+
 	#[cfg(feature = "client")]
 	pub fn pressed(&self, key: KeyCode) -> bool {
 		self.pressed_keys.contains(&key)
@@ -264,53 +696,196 @@ impl<M> Context<M> {
 }
 
 impl<M> Context<M> {
+	/// Handles requests, responses and inputs, which are not passed to [`App::on_packet`].
+	/// Other packets are returned as-is.
+	fn on_internal_packet<A: App<M>>(
+		ctx: &mut Context<M>,
+		packet: TrustedPacket<M>,
+	) -> Option<TrustedPacket<M>> {
+		let client_id = packet.client_id;
+
+		match packet.inner {
+			Packet::Request { id, message, .. } => {
+				if let Some(response) = A::on_request(ctx, client_id, id, message) {
+					ctx.respond(client_id, id, response);
+				}
+			}
+			Packet::Response { id, message, .. } => {
+				ctx.receive_response(client_id, id, message);
+			}
+			Packet::Input { tick, input } => {
+				ctx.queue_input(client_id, tick, input);
+			}
+			Packet::InputAck {
+				tick,
+				position,
+				rotation,
+				..
+			} => {
+				ctx.reconcile(tick, position, rotation);
+			}
+			// only the server receives ownership requests
+			#[cfg(feature = "server")]
+			Packet::RequestOwnership { id, owner } => {
+				if ctx.can_own(owner) && A::approve_ownership(ctx, client_id, id, owner) {
+					ctx.set_owner(id, owner);
+				}
+			}
+			inner => return Some(inner.into_trusted(client_id)),
+		}
+
+		None
+	}
+
+	/// Creates an instance received from the server, owned by `owner`.
+	fn create_instance_remote(
+		&mut self,
+		owner: ClientId,
+		id: InstanceId,
+		options: &CreateInstance,
+	) {
+		// instances created by this client have already been spawned
+		if self.handles.contains_key(&id) {
+			self.properties.insert(id, options.properties.clone());
+			return;
+		}
+
+		info!("creating instance {id:?} with options {options:?}");
+
+		let instance = self.add_instance_local(options.model_id, options.to_builder());
+
+		self.handles.insert(id, instance);
+		self.instance_ids.insert(instance, id);
+		self.owners.insert(id, owner);
+		self.properties.insert(id, options.properties.clone());
+	}
+
+	/// Applies an update to an instance received from the server.
+	fn update_instance_remote(&mut self, id: InstanceId, delta: &UpdateInstance) {
+		let Some(&instance) = self.handles.get(&id) else {
+			return;
+		};
+
+		// remote instances are moved smoothly in `Context::interpolate`
+		if delta.is_transform_only()
+			&& !self.network.interpolation_delay.is_zero()
+			&& Some(id) != self.instance_id
+		{
+			self.interpolation.push(id, delta.position, delta.rotation);
+		} else {
+			self.update_instance_local(instance, delta);
+		}
+	}
+
 	fn on_packet<A: App<M>>(ctx: &mut Context<M>, packet: TrustedPacket<M>) {
+		let Some(packet) = Self::on_internal_packet::<A>(ctx, packet) else {
+			return;
+		};
 		let client_id = packet.client_id;
 
 		match packet.inner {
-			Packet::Custom(..) => {}
-			Packet::CreateClient { instance_id } => {
+			Packet::Custom(..)
+			| Packet::Message { .. }
+			| Packet::Snapshot { .. }
+			| Packet::AckSnapshot { .. }
+			| Packet::Request { .. }
+			| Packet::Response { .. }
+			| Packet::Input { .. }
+			| Packet::InputAck { .. }
+			| Packet::JoinRoom { .. }
+			| Packet::SetRoomState { .. }
+			| Packet::RequestOwnership { .. }
+			| Packet::Ping { .. }
+			| Packet::Pong { .. } => {}
+			Packet::CreateClient {
+				instance_id,
+				ref identity,
+			} => {
+				if let Some(identity) = identity {
+					ctx.identities.insert(client_id, identity.clone());
+				}
+
 				let (model_id, instance) = A::create_player(ctx);
 				let instance = ctx.add_instance_local(model_id, instance);
 
 				ctx.handles.insert(instance_id, instance);
 				ctx.instance_ids.insert(instance, instance_id);
+				ctx.owners.insert(instance_id, client_id);
 				ctx.clients.insert(client_id, instance_id);
 			}
-			Packet::DeleteClient => {
-				let Some(instance_id) = ctx.clients.remove(&client_id) else {
-					return;
-				};
+			Packet::DeleteClient { ref reason } => {
+				ctx.identities.remove(&client_id);
+				ctx.rooms.remove(&client_id);
+
+				if let Some(instance_id) = ctx.clients.remove(&client_id) {
+					ctx.remove_instance_local(instance_id);
+				}
 
-				ctx.remove_instance_local(instance_id);
+				ctx.fail_requests(Some(client_id));
+				ctx.prediction.remove_client(client_id);
+
+				A::on_disconnect(ctx, client_id, reason);
 			}
 			Packet::CreateInstance { ref options, id } => {
-				info!("creating instance {id:?} with options {options:?}");
-
+				// the local player is created when connecting
 				if Some(id) == ctx.instance_id {
 					return;
 				}
 
-				let instance = ctx.add_instance_local(options.model_id, options.to_builder());
-
-				ctx.handles.insert(id, instance);
-				ctx.instance_ids.insert(instance, id);
+				ctx.create_instance_remote(client_id, id, options);
 			}
 			Packet::DeleteInstance { id } => {
 				ctx.remove_instance_local(id);
 			}
 			Packet::UpdateInstance { id, ref delta } => {
-				let Some(&mut instance) = ctx.handles.get_mut(&id) else {
-					return;
-				};
+				ctx.update_instance_remote(id, delta);
+			}
+			Packet::CreateJoint { ref options, id } => {
+				if let (Some(&a), Some(&b)) = (
+					ctx.handles.get(&options.instance_a),
+					ctx.handles.get(&options.instance_b),
+				) {
+					ctx.add_joint_local(id, a, b, options.data.to_joint());
+				}
+			}
+			Packet::DeleteJoint { id } => {
+				ctx.remove_joint_local(id);
+			}
+			Packet::UpdateJoint { id, ref data } => {
+				ctx.update_joint_local(id, |joint| *joint = data.to_joint());
+			}
+			Packet::SetGravity { gravity } => {
+				ctx.set_gravity_local(gravity);
+			}
+			Packet::Spawned { local_id, id } => {
+				ctx.receive_spawned(local_id, id);
+			}
+			Packet::SetOwner { id, owner } => {
+				ctx.owners.insert(id, owner);
+
+				// the new owner moves the instance itself from now on
+				if Some(owner) == ctx.client_id {
+					ctx.interpolation.remove(id);
+				}
+			}
+			Packet::SetProperty {
+				id,
+				ref key,
+				ref value,
+			} => {
+				ctx.receive_property(id, key, value);
+			}
+			Packet::Room { room, state } => {
+				if let Some(id) = ctx.client_id {
+					ctx.rooms.insert(id, room);
+				}
 
-				instance.update(ctx, |i, p| {
-					delta.apply(p, i);
-				});
+				ctx.room_states.insert(room, state);
 			}
 			Packet::Connected { instance_id } => {
 				ctx.client_id = Some(client_id);
 				ctx.instance_id = Some(instance_id);
+				ctx.owners.insert(instance_id, client_id);
 			}
 		}
 
@@ -324,16 +899,26 @@ impl<M> Context<M> {
 	) {
 		std::hint::spin_loop();
 
+		self.poll_server::<A>();
+
 		while let Ok(packet) = self.packet_rx.try_recv() {
 			Self::on_packet::<A>(self, packet);
 		}
 
+		self.expire_requests();
+		self.interpolate();
+
 		let delta = self.last_physics.elapsed();
 
-		// physics 60fps
-		if delta.as_secs_f32() >= 1.0 / 60.0 {
+		if delta.as_secs_f32() >= self.physics.integration.dt {
 			self.last_physics = time::Instant::now();
+
+			let tick = self.predict_input(app);
+
+			self.simulate_inputs::<A>();
 			self.physics_update();
+			self.record_prediction(tick);
+			self.acknowledge_inputs();
 			app.on_fixed_update(self);
 		}
 
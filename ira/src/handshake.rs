@@ -0,0 +1,150 @@
+//! The handshake performed when a client connects to a server.
+//!
+//! Before anything else is sent, the client sends a [`Handshake`] describing its
+//! build, and the server replies with a [`HandshakeResponse`]. If the server rejects
+//! the client, the connection is closed and the reason is returned as a
+//! [`server::Error::Rejected`](crate::server::Error::Rejected) on the client.
+
+use std::{fmt, hash::Hasher};
+
+use ira_drum::Drum;
+
+use crate::App;
+
+/// The version of the network protocol. This is bumped whenever the framing or
+/// handshake changes in a way that is incompatible with older builds.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The first frame sent by a client when connecting.
+#[derive(Debug, Clone, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub struct Handshake {
+	pub protocol_version: u32,
+	/// The id of the application, see [`App::app_id`].
+	pub app_id: String,
+	/// A hash of the version of ira, [`App::PROTOCOL_VERSION`], the names of the message
+	/// and input types and the contents of the drum, which must match exactly.
+	pub schema_hash: u64,
+	/// The credentials of the client, see [`App::credentials`] and [`App::authenticate`].
+	pub credentials: Vec<u8>,
+}
+
+/// The reply of the server to a [`Handshake`].
+#[derive(Debug, Clone, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub enum HandshakeResponse {
+	Accepted,
+	Rejected(RejectReason),
+}
+
+/// The reason a server rejected a client.
+#[derive(Debug, Clone, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub enum RejectReason {
+	/// The client uses a different version of the network protocol.
+	ProtocolVersion { expected: u32, received: u32 },
+	/// The client is running a different application.
+	AppId { expected: String, received: String },
+	/// The client uses a different version of ira or of the application's
+	/// [`App::PROTOCOL_VERSION`], different message or input types, or a different drum.
+	Schema,
+	/// The handshake could not be decoded.
+	Malformed,
+	/// The server did not accept the client's credentials.
+	Unauthorized(String),
+}
+
+impl fmt::Display for RejectReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::ProtocolVersion { expected, received } => write!(
+				f,
+				"protocol version mismatch (server uses {expected}, client uses {received})"
+			),
+			Self::AppId { expected, received } => write!(
+				f,
+				"application mismatch (server runs {expected:?}, client runs {received:?})"
+			),
+			Self::Schema => write!(f, "packet schema or drum mismatch"),
+			Self::Malformed => write!(f, "malformed handshake"),
+			Self::Unauthorized(reason) => write!(f, "unauthorized: {reason}"),
+		}
+	}
+}
+
+impl Handshake {
+	/// Creates the handshake for an application and its drum, including the
+	/// credentials of the client.
+	#[must_use]
+	pub fn new<A: App<M>, M>(drum: &Drum) -> Self {
+		let mut hasher = Fnv::default();
+
+		// the packets of ira itself only change between its versions
+		hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+		hasher.write(&A::PROTOCOL_VERSION.to_le_bytes());
+		// catches clients built with different message or input types, though not changes to them
+		hasher.write(std::any::type_name::<M>().as_bytes());
+		hasher.write(std::any::type_name::<A::Input>().as_bytes());
+		drum.hash_content(&mut hasher);
+
+		Self {
+			protocol_version: PROTOCOL_VERSION,
+			app_id: A::app_id().into(),
+			schema_hash: hasher.finish(),
+			credentials: A::credentials(),
+		}
+	}
+
+	/// Checks that a client's handshake matches this one. Credentials are not
+	/// checked here, see [`App::authenticate`].
+	///
+	/// # Errors
+	///
+	/// Returns the reason the client should be rejected.
+	pub fn validate(&self, client: &Handshake) -> Result<(), RejectReason> {
+		if self.protocol_version != client.protocol_version {
+			return Err(RejectReason::ProtocolVersion {
+				expected: self.protocol_version,
+				received: client.protocol_version,
+			});
+		}
+
+		if self.app_id != client.app_id {
+			return Err(RejectReason::AppId {
+				expected: self.app_id.clone(),
+				received: client.app_id.clone(),
+			});
+		}
+
+		if self.schema_hash != client.schema_hash {
+			return Err(RejectReason::Schema);
+		}
+
+		Ok(())
+	}
+}
+
+/// A 64-bit FNV-1a hasher, which (unlike the standard library's hasher) is
+/// guaranteed to produce the same hash on every build and platform.
+struct Fnv(u64);
+
+impl Default for Fnv {
+	fn default() -> Self {
+		Self(0xcbf2_9ce4_8422_2325)
+	}
+}
+
+impl Hasher for Fnv {
+	fn finish(&self) -> u64 {
+		self.0
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for byte in bytes {
+			self.0 ^= u64::from(*byte);
+			self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+		}
+	}
+
+	fn write_usize(&mut self, i: usize) {
+		// usize differs in size between platforms
+		self.write(&(i as u64).to_le_bytes());
+	}
+}
@@ -0,0 +1,175 @@
+use std::{
+	collections::{BTreeMap, VecDeque},
+	time::{Duration, Instant},
+};
+
+use glam::{Quat, Vec3};
+
+use crate::{
+	game::Context,
+	packet::{UpdateBody, UpdateInstance},
+	server::InstanceId,
+};
+
+/// A pose of an instance received from the server.
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+	received: Instant,
+	position: Vec3,
+	rotation: Quat,
+}
+
+/// The samples received for an instance, oldest first.
+#[derive(Default)]
+struct Buffer {
+	samples: VecDeque<Sample>,
+	/// Whether the instance has been left at its final pose, after running out
+	/// of samples to interpolate or extrapolate from.
+	settled: bool,
+}
+
+/// A pose computed from the buffered samples of an instance.
+struct Pose {
+	position: Vec3,
+	rotation: Quat,
+	velocity: Vec3,
+	angular_velocity: Vec3,
+}
+
+/// Buffers the poses of remote instances, so they can be shown slightly in the
+/// past and move smoothly between updates.
+#[derive(Default)]
+pub(crate) struct Interpolation {
+	buffers: BTreeMap<InstanceId, Buffer>,
+}
+
+impl Interpolation {
+	/// Buffers a pose received for an instance.
+	pub(crate) fn push(&mut self, id: InstanceId, position: Vec3, rotation: Quat) {
+		let buffer = self.buffers.entry(id).or_default();
+
+		buffer.settled = false;
+		buffer.samples.push_back(Sample {
+			received: Instant::now(),
+			position,
+			rotation,
+		});
+	}
+
+	/// Forgets the samples of an instance that has been removed.
+	pub(crate) fn remove(&mut self, id: InstanceId) {
+		self.buffers.remove(&id);
+	}
+
+	/// Computes the pose of every buffered instance at `time`, extrapolating up to
+	/// `max_extrapolation` past the latest sample.
+	fn poses(&mut self, time: Instant, max_extrapolation: Duration) -> Vec<(InstanceId, Pose)> {
+		self.buffers
+			.iter_mut()
+			.filter_map(|(id, buffer)| {
+				// keep a single sample older than `time` to interpolate from
+				while buffer.samples.len() > 2 && buffer.samples[1].received <= time {
+					buffer.samples.pop_front();
+				}
+
+				if buffer.settled {
+					return None;
+				}
+
+				let (pose, settled) = sample(&buffer.samples, time, max_extrapolation)?;
+
+				// the final pose is applied once, so the body can fall asleep
+				buffer.settled = settled;
+
+				Some((*id, pose))
+			})
+			.collect()
+	}
+}
+
+/// Computes the pose at `time` from samples sorted by the time they were received,
+/// and whether it is final until another sample is received.
+fn sample(
+	samples: &VecDeque<Sample>,
+	time: Instant,
+	max_extrapolation: Duration,
+) -> Option<(Pose, bool)> {
+	let still = |sample: &Sample| Pose {
+		position: sample.position,
+		rotation: sample.rotation,
+		velocity: Vec3::ZERO,
+		angular_velocity: Vec3::ZERO,
+	};
+
+	let (from, to) = match (samples.front(), samples.get(1)) {
+		(None, _) => return None,
+		// nothing to move towards yet
+		(Some(only), None) => return Some((still(only), true)),
+		(Some(from), Some(to)) => (from, to),
+	};
+
+	let span = to.received.duration_since(from.received).as_secs_f32();
+
+	if span <= f32::EPSILON {
+		return Some((still(to), time >= to.received));
+	}
+
+	// past the latest sample, the instance keeps moving for a short while
+	let limit = to.received + max_extrapolation;
+	let time = time.clamp(from.received, limit.max(to.received));
+	let t = time.duration_since(from.received).as_secs_f32() / span;
+
+	let velocity = (to.position - from.position) / span;
+	let angular_velocity = (to.rotation * from.rotation.inverse()).to_scaled_axis() / span;
+	let extrapolating = time > to.received;
+
+	let settled = time >= limit;
+	let pose = Pose {
+		position: from.position.lerp(to.position, t),
+		rotation: if extrapolating {
+			Quat::from_scaled_axis(angular_velocity * (t - 1.0) * span) * to.rotation
+		} else {
+			from.rotation.slerp(to.rotation, t)
+		},
+		// bodies stop at the end of extrapolation rather than drifting away
+		velocity: if settled { Vec3::ZERO } else { velocity },
+		angular_velocity: if settled {
+			Vec3::ZERO
+		} else {
+			angular_velocity
+		},
+	};
+
+	Some((pose, settled))
+}
+
+impl<M> Context<M> {
+	/// Moves remote instances to their interpolated poses, shown
+	/// [`NetworkConfig::interpolation_delay`](crate::server::NetworkConfig::interpolation_delay)
+	/// in the past.
+	pub(crate) fn interpolate(&mut self) {
+		let Some(time) = Instant::now().checked_sub(self.network.interpolation_delay) else {
+			return;
+		};
+
+		for (id, pose) in self
+			.interpolation
+			.poses(time, self.network.max_extrapolation)
+		{
+			let Some(&instance) = self.handles.get(&id) else {
+				continue;
+			};
+
+			let mut delta = UpdateInstance::transform(pose.position, pose.rotation);
+
+			delta.body = Some(UpdateBody::Velocity {
+				velocity: pose.velocity,
+				angular_velocity: pose.angular_velocity,
+			});
+
+			instance.update(self, |i, p| {
+				delta.apply(p, i);
+			});
+		}
+	}
+}
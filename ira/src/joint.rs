@@ -0,0 +1,165 @@
+use rapier3d::dynamics::{GenericJoint, ImpulseJoint, ImpulseJointHandle};
+
+use crate::{
+	game::Context,
+	packet::{CreateJoint, JointData, Packet},
+	physics::InstanceHandle,
+	server::JointId,
+	Body,
+};
+
+/// A joint connecting two instances.
+#[derive(Debug, Clone, Copy)]
+pub struct Joint {
+	pub handle: ImpulseJointHandle,
+	pub instance_a: InstanceHandle,
+	pub instance_b: InstanceHandle,
+}
+
+impl<M> Context<M> {
+	/// Connects two instances with a joint, returning its id.
+	///
+	/// Any of rapier's joint builders can be used here, such as [`rapier3d::dynamics::FixedJointBuilder`],
+	/// [`rapier3d::dynamics::RevoluteJointBuilder`], [`rapier3d::dynamics::PrismaticJointBuilder`],
+	/// [`rapier3d::dynamics::SphericalJointBuilder`] and [`rapier3d::dynamics::RopeJointBuilder`],
+	/// including their limits and motors.
+	///
+	/// Returns `None` if either instance does not exist or does not have a rigidbody.
+	///
+	/// The local version of this method is [`Context::add_joint_local`].
+	#[cfg(feature = "server")]
+	pub fn add_joint(
+		&mut self,
+		instance_a: InstanceHandle,
+		instance_b: InstanceHandle,
+		joint: impl Into<GenericJoint>,
+	) -> Option<JointId> {
+		use std::sync::atomic::Ordering;
+
+		let joint = joint.into();
+		let options = CreateJoint {
+			instance_a: *self.instance_ids.get(&instance_a)?,
+			instance_b: *self.instance_ids.get(&instance_b)?,
+			data: JointData::from_joint(&joint),
+		};
+
+		let id = JointId::new(self.next_joint_id.fetch_add(1, Ordering::SeqCst));
+
+		self.add_joint_local(id, instance_a, instance_b, joint)?;
+
+		let _ = self.packet_tx.send(Packet::CreateJoint { options, id });
+
+		Some(id)
+	}
+
+	/// Connects two instances with a joint.
+	///
+	/// The joint is created once the server has assigned it an id.
+	#[cfg(not(feature = "server"))]
+	pub fn add_joint(
+		&mut self,
+		instance_a: InstanceHandle,
+		instance_b: InstanceHandle,
+		joint: impl Into<GenericJoint>,
+	) {
+		let (Some(&a), Some(&b)) = (
+			self.instance_ids.get(&instance_a),
+			self.instance_ids.get(&instance_b),
+		) else {
+			return;
+		};
+
+		let _ = self.packet_tx.send(Packet::CreateJoint {
+			id: JointId::NONE,
+			options: CreateJoint {
+				instance_a: a,
+				instance_b: b,
+				data: JointData::from_joint(&joint.into()),
+			},
+		});
+	}
+
+	/// Connects two instances with a joint, without notifying the server.
+	///
+	/// Returns `None` if either instance does not exist or does not have a rigidbody.
+	///
+	/// The networked version of this method is [`Context::add_joint`].
+	pub fn add_joint_local(
+		&mut self,
+		id: JointId,
+		instance_a: InstanceHandle,
+		instance_b: InstanceHandle,
+		joint: impl Into<GenericJoint>,
+	) -> Option<ImpulseJointHandle> {
+		let Body::Rigid(body_a) = self.instances.get(*instance_a)?.body else {
+			return None;
+		};
+
+		let Body::Rigid(body_b) = self.instances.get(*instance_b)?.body else {
+			return None;
+		};
+
+		let handle = self
+			.physics
+			.impulse_joints
+			.insert(body_a, body_b, joint, true);
+
+		self.joints.insert(
+			id,
+			Joint {
+				handle,
+				instance_a,
+				instance_b,
+			},
+		);
+
+		Some(handle)
+	}
+
+	/// Returns the joint with the given id, if it exists.
+	#[must_use]
+	pub fn joint(&self, id: JointId) -> Option<&ImpulseJoint> {
+		let joint = self.joints.get(&id)?;
+
+		self.physics.impulse_joints.get(joint.handle)
+	}
+
+	/// Updates the limits, motors or frames of a joint, then notifies the server.
+	pub fn update_joint<F>(&mut self, id: JointId, update: F)
+	where
+		F: FnOnce(&mut GenericJoint),
+	{
+		let Some(data) = self.update_joint_local(id, update) else {
+			return;
+		};
+
+		let _ = self.packet_tx.send(Packet::UpdateJoint { id, data });
+	}
+
+	/// Updates a joint without notifying the server, returning its new state.
+	pub fn update_joint_local<F>(&mut self, id: JointId, update: F) -> Option<JointData>
+	where
+		F: FnOnce(&mut GenericJoint),
+	{
+		let joint = self.joints.get(&id)?;
+		let joint = self.physics.impulse_joints.get_mut(joint.handle)?;
+
+		update(&mut joint.data);
+
+		Some(JointData::from_joint(&joint.data))
+	}
+
+	/// Removes a joint, then notifies the server.
+	pub fn remove_joint(&mut self, id: JointId) {
+		self.remove_joint_local(id);
+
+		let _ = self.packet_tx.send(Packet::DeleteJoint { id });
+	}
+
+	/// Removes a joint without notifying the server.
+	pub fn remove_joint_local(&mut self, id: JointId) -> Option<ImpulseJoint> {
+		let joint = self.joints.remove(&id)?;
+
+		self.physics.impulse_joints.remove(joint.handle, true)
+	}
+}
@@ -0,0 +1,97 @@
+use rapier3d::{
+	geometry::{Group, InteractionGroups},
+	pipeline::QueryFilter,
+};
+use tracing::warn;
+
+/// A registry of named collision layers.
+///
+/// Each layer maps to one bit of a rapier [`Group`], so at most [`Layers::MAX`]
+/// layers can be declared. Layers should be declared in the same order on every
+/// client and the server, since only the resulting bits are sent over the network.
+/// The simplest way to do this is with [`App::layers`](crate::App::layers).
+///
+/// # Examples
+///
+/// ```rust
+/// use ira::layer::Layers;
+///
+/// let mut layers = Layers::default();
+///
+/// let player = layers.register("player");
+/// let terrain = layers.register("terrain");
+///
+/// assert_eq!(layers.get("player"), Some(player));
+/// assert_eq!(layers.mask(&["player", "terrain"]), player | terrain);
+/// ```
+#[derive(Debug, Default)]
+pub struct Layers {
+	names: Vec<Box<str>>,
+}
+
+impl Layers {
+	/// The maximum number of layers that can be declared.
+	pub const MAX: usize = 32;
+
+	/// Declares a new layer, returning its group.
+	///
+	/// Declaring a layer that already exists returns its existing group.
+	///
+	/// # Panics
+	///
+	/// Panics if more than [`Self::MAX`] layers are declared.
+	pub fn register(&mut self, name: &str) -> Group {
+		if let Some(group) = self.get(name) {
+			return group;
+		}
+
+		assert!(
+			self.names.len() < Self::MAX,
+			"at most {} collision layers can be declared",
+			Self::MAX
+		);
+
+		self.names.push(name.into());
+
+		Group::from_bits_retain(1 << (self.names.len() - 1))
+	}
+
+	/// Returns the group of the layer with the given name, if it exists.
+	#[must_use]
+	pub fn get(&self, name: &str) -> Option<Group> {
+		self.names
+			.iter()
+			.position(|n| &**n == name)
+			.map(|i| Group::from_bits_retain(1 << i))
+	}
+
+	/// Returns the union of the named layers.
+	///
+	/// Layers that have not been declared are ignored.
+	#[must_use]
+	pub fn mask(&self, names: &[&str]) -> Group {
+		names.iter().fold(Group::NONE, |mask, name| {
+			let Some(group) = self.get(name) else {
+				warn!(layer = name, "unknown collision layer");
+				return mask;
+			};
+
+			mask | group
+		})
+	}
+
+	/// Returns interaction groups that are members of the `memberships` layers
+	/// and interact with the `filter` layers.
+	#[must_use]
+	pub fn groups(&self, memberships: &[&str], filter: &[&str]) -> InteractionGroups {
+		InteractionGroups::new(self.mask(memberships), self.mask(filter))
+	}
+
+	/// Returns a query filter that only matches colliders interacting with the given groups.
+	///
+	/// This can be used for scene queries such as raycasts and shape casts.
+	#[must_use]
+	pub fn query_filter(&self, memberships: &[&str], filter: &[&str]) -> QueryFilter<'static> {
+		QueryFilter::new().groups(self.groups(memberships, filter))
+	}
+}
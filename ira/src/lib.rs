@@ -1,4 +1,5 @@
-#![feature(iter_array_chunks)]
+#![feature(associated_type_defaults)]
+#![cfg_attr(feature = "collision", feature(iter_array_chunks))]
 #![warn(clippy::pedantic)]
 #![allow(
 	clippy::unused_async,
@@ -24,15 +25,24 @@ pub mod client;
 pub mod drum;
 pub mod extra;
 pub mod game;
+pub mod handshake;
+pub(crate) mod interpolation;
+pub mod joint;
+pub mod layer;
 #[cfg(feature = "client")]
 pub mod light;
 pub mod material;
 pub mod model;
 pub mod packet;
 pub mod physics;
+pub(crate) mod prediction;
+pub mod property;
 #[cfg(feature = "client")]
 pub(crate) mod render;
+pub mod rpc;
 pub mod server;
+pub mod snapshot;
+pub mod transport;
 
 #[cfg(feature = "client")]
 pub use camera::*;
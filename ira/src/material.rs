@@ -13,6 +13,12 @@ pub struct GpuMesh {
 	/// The bounding box of the mesh.
 	pub min: glam::Vec3,
 	pub max: glam::Vec3,
+	/// The vertex positions of the mesh, kept around to build colliders from.
+	#[cfg(feature = "collision")]
+	pub positions: Box<[glam::Vec3]>,
+	/// The triangle indices of the mesh, kept around to build colliders from.
+	#[cfg(feature = "collision")]
+	pub indices: Box<[u32]>,
 
 	#[cfg(feature = "client")]
 	pub vertex_buffer: wgpu::Buffer,
@@ -249,6 +255,10 @@ impl MeshExt for ira_drum::Mesh {
 			min: self.min.into(),
 			max: self.max.into(),
 			num_indices: self.indices.len() as u32,
+			#[cfg(feature = "collision")]
+			positions: self.vertices.iter().map(|v| v.position.into()).collect(),
+			#[cfg(feature = "collision")]
+			indices: self.indices.clone(),
 			#[cfg(feature = "client")]
 			vertex_buffer,
 			#[cfg(feature = "client")]
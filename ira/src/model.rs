@@ -1,12 +1,25 @@
 use glam::{Quat, Vec3};
 use ira_drum::Handle;
+use nalgebra::Point3;
+#[cfg(feature = "collision")]
+use nalgebra::{DMatrix, Vector3};
 use rapier3d::{
 	dynamics::{RigidBody, RigidBodyBuilder, RigidBodyHandle},
-	geometry::{ColliderBuilder, ColliderHandle},
+	geometry::{ColliderBuilder, ColliderHandle, Group, InteractionGroups, SharedShape},
+	math::Isometry,
+};
+#[cfg(feature = "collision")]
+use rapier3d::{
+	geometry::TriMesh,
+	parry::{
+		query::{Ray, RayCast},
+		transformation::vhacd::VHACDParameters,
+	},
 };
 
 use crate::{
 	physics::{BoundingBox, InstanceHandle, PhysicsState},
+	property::Properties,
 	GpuDrum, GpuMesh,
 };
 
@@ -17,7 +30,6 @@ use crate::render::model::GpuInstance;
 pub struct GpuModel {
 	pub(crate) name: Box<str>,
 
-	#[cfg(feature = "client")]
 	pub(crate) meshes: GpuMeshHandles,
 	#[cfg(feature = "client")]
 	pub(crate) instance_buffer: wgpu::Buffer,
@@ -26,6 +38,7 @@ pub struct GpuModel {
 	pub(crate) instances: Vec<GpuInstance>,
 	pub(crate) handles: Vec<InstanceHandle>,
 	pub(crate) bounds: BoundingBox,
+	pub(crate) collision: Option<ira_drum::Collision>,
 
 	#[cfg(feature = "client")]
 	pub(crate) last_instance_count: usize,
@@ -46,9 +59,8 @@ impl GpuModel {
 		Self {
 			handles: Vec::new(),
 			bounds: BoundingBox { min, max },
+			collision: None,
 			name,
-
-			#[cfg(feature = "client")]
 			meshes,
 			#[cfg(feature = "client")]
 			instance_buffer: Self::create_instance_buffer(device, &[]),
@@ -76,6 +88,155 @@ impl GpuModel {
 	pub fn bounds(&self) -> BoundingBox {
 		self.bounds
 	}
+
+	/// Returns the collision shape baked into the drum for this model, if any.
+	#[must_use]
+	pub fn collision(&self) -> Option<&ira_drum::Collision> {
+		self.collision.as_ref()
+	}
+
+	/// Creates a collider from the collision shape baked into the drum, scaled by `scale`.
+	///
+	/// Returns `None` if the model has no baked collision shape, or if a baked convex
+	/// hull could not be rebuilt. Shapes can be baked with `ira pack --collision`.
+	#[must_use]
+	pub fn to_baked_collider(&self, scale: Vec3) -> Option<ColliderBuilder> {
+		let scaled = |points: &[ira_drum::Vec3]| {
+			points
+				.iter()
+				.map(|&p| Point3::from(Vec3::from(p) * scale))
+				.collect::<Vec<_>>()
+		};
+
+		match self.collision.as_ref()? {
+			ira_drum::Collision::ConvexHull { points } => {
+				ColliderBuilder::convex_hull(&scaled(points))
+			}
+			ira_drum::Collision::TriMesh { vertices, indices } => {
+				Some(ColliderBuilder::trimesh(scaled(vertices), indices.to_vec()))
+			}
+			ira_drum::Collision::Compound { parts } => Some(ColliderBuilder::compound(
+				parts
+					.iter()
+					.filter_map(|points| SharedShape::convex_hull(&scaled(points)))
+					.map(|shape| (Isometry::identity(), shape))
+					.collect(),
+			)),
+		}
+	}
+}
+
+/// Colliders built from the meshes of a model, which need the `collision` feature to keep
+/// the mesh data around after it has been uploaded.
+#[cfg(feature = "collision")]
+impl GpuModel {
+	/// Returns the vertices and triangles of all meshes in the model, scaled by `scale`.
+	fn triangles(&self, drum: &GpuDrum, scale: Vec3) -> (Vec<Point3<f32>>, Vec<[u32; 3]>) {
+		let mut vertices = Vec::new();
+		let mut indices = Vec::new();
+
+		for mesh in self
+			.meshes
+			.opaque
+			.iter()
+			.chain(self.meshes.transparent.iter())
+		{
+			let mesh = mesh.resolve(&drum.meshes);
+			let offset = vertices.len() as u32;
+
+			vertices.extend(mesh.positions.iter().map(|p| Point3::from(*p * scale)));
+			indices.extend(
+				mesh.indices
+					.iter()
+					.array_chunks()
+					.map(|[a, b, c]| [a + offset, b + offset, c + offset]),
+			);
+		}
+
+		(vertices, indices)
+	}
+
+	/// Creates a convex hull collider that wraps all meshes of the model.
+	///
+	/// Returns `None` if the hull could not be computed (e.g. if the model is flat).
+	#[must_use]
+	pub fn to_convex_hull(&self, drum: &GpuDrum, scale: Vec3) -> Option<ColliderBuilder> {
+		let (vertices, _) = self.triangles(drum, scale);
+
+		ColliderBuilder::convex_hull(&vertices)
+	}
+
+	/// Creates a triangle mesh collider that exactly matches the meshes of the model.
+	///
+	/// Triangle meshes have no volume, so they are best used for static or kinematic
+	/// instances such as terrain. For dynamic instances, see [`Self::to_convex_decomposition`].
+	pub fn to_trimesh(&self, drum: &GpuDrum, scale: Vec3) -> ColliderBuilder {
+		let (vertices, indices) = self.triangles(drum, scale);
+
+		ColliderBuilder::trimesh(vertices, indices)
+	}
+
+	/// Creates a compound collider by decomposing the model into convex parts with VHACD.
+	///
+	/// This can be slow for detailed models, so consider baking it ahead of time.
+	pub fn to_convex_decomposition(&self, drum: &GpuDrum, scale: Vec3) -> ColliderBuilder {
+		self.to_convex_decomposition_with_params(drum, scale, &VHACDParameters::default())
+	}
+
+	/// Creates a compound collider by decomposing the model into convex parts with VHACD,
+	/// using the provided parameters.
+	pub fn to_convex_decomposition_with_params(
+		&self,
+		drum: &GpuDrum,
+		scale: Vec3,
+		params: &VHACDParameters,
+	) -> ColliderBuilder {
+		let (vertices, indices) = self.triangles(drum, scale);
+
+		ColliderBuilder::convex_decomposition_with_params(&vertices, &indices, params)
+	}
+
+	/// Creates a heightfield collider by sampling the top surface of the model
+	/// on a grid of `rows` by `columns` points.
+	///
+	/// Heightfields are always centered on the origin, so the grid covers the largest
+	/// extent of the model in each direction. Points that miss the model are placed
+	/// at its lowest point.
+	///
+	/// # Panics
+	///
+	/// Panics if `rows` or `columns` is less than 2.
+	pub fn to_heightfield(
+		&self,
+		drum: &GpuDrum,
+		scale: Vec3,
+		rows: usize,
+		columns: usize,
+	) -> ColliderBuilder {
+		assert!(
+			rows >= 2 && columns >= 2,
+			"heightfields need at least 2x2 points"
+		);
+
+		let (vertices, indices) = self.triangles(drum, scale);
+		let mesh = TriMesh::new(vertices, indices);
+		let aabb = mesh.local_aabb();
+
+		let width = aabb.mins.x.abs().max(aabb.maxs.x.abs()) * 2.0;
+		let depth = aabb.mins.z.abs().max(aabb.maxs.z.abs()) * 2.0;
+		let top = aabb.maxs.y + 1.0;
+
+		let heights = DMatrix::from_fn(rows, columns, |row, column| {
+			let x = (column as f32 / (columns - 1) as f32 - 0.5) * width;
+			let z = (row as f32 / (rows - 1) as f32 - 0.5) * depth;
+			let ray = Ray::new(Point3::new(x, top, z), -Vector3::y());
+
+			mesh.cast_local_ray(&ray, f32::MAX, false)
+				.map_or(aabb.mins.y, |toi| top - toi)
+		});
+
+		ColliderBuilder::heightfield(heights, Vector3::new(width, 1.0, depth))
+	}
 }
 
 #[must_use]
@@ -187,6 +348,11 @@ pub struct InstanceBuilder {
 
 	pub rigidbody: Option<RigidBodyBuilder>,
 	pub collider: Option<ColliderBuilder>,
+
+	pub collision_groups: Option<InteractionGroups>,
+	pub solver_groups: Option<InteractionGroups>,
+
+	pub properties: Properties,
 }
 
 impl Default for InstanceBuilder {
@@ -197,6 +363,9 @@ impl Default for InstanceBuilder {
 			scale: Vec3::ONE,
 			rigidbody: None,
 			collider: None,
+			collision_groups: None,
+			solver_groups: None,
+			properties: Properties::default(),
 		}
 	}
 }
@@ -260,6 +429,35 @@ impl InstanceBuilder {
 		self.collider = Some(collider);
 		self
 	}
+
+	/// Sets the layers the instance's collider is a member of, and the layers it
+	/// interacts with. This filters both contacts and scene queries.
+	///
+	/// Both groups can be created with [`Layers`](crate::layer::Layers).
+	/// By default, the instance is a member of and interacts with every layer.
+	pub fn collision_groups(mut self, memberships: Group, filter: Group) -> Self {
+		self.collision_groups = Some(InteractionGroups::new(memberships, filter));
+		self
+	}
+
+	/// Sets the layers the instance's collider is a member of, and the layers it
+	/// exchanges contact forces with. Unlike [`Self::collision_groups`], contacts
+	/// are still reported for layers filtered out here.
+	///
+	/// By default, the instance is a member of and interacts with every layer.
+	pub fn solver_groups(mut self, memberships: Group, filter: Group) -> Self {
+		self.solver_groups = Some(InteractionGroups::new(memberships, filter));
+		self
+	}
+
+	/// Sets a replicated property of the instance, which is sent along with it when
+	/// created with [`Context::add_instance`](crate::Context::add_instance).
+	///
+	/// See [`Context::set_property`](crate::Context::set_property) for changing it afterwards.
+	pub fn property<T: bitcode::Encode + ?Sized>(mut self, key: &str, value: &T) -> Self {
+		self.properties.set(key, value);
+		self
+	}
 }
 
 /// The body of an instance.
@@ -362,13 +560,16 @@ impl ModelExt for ira_drum::Model {
 		drum: &GpuDrum,
 		#[cfg(feature = "client")] device: &wgpu::Device,
 	) -> GpuModel {
-		GpuModel::new(
+		let mut model = GpuModel::new(
 			drum,
 			self.name,
 			self.meshes.into(),
 			#[cfg(feature = "client")]
 			device,
-		)
+		);
+
+		model.collision = self.collision;
+		model
 	}
 }
 
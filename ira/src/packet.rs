@@ -1,12 +1,26 @@
-use std::io::{self, Write};
+use std::io;
 
 use glam::{Quat, Vec3};
-use rapier3d::{dynamics::RigidBodyBuilder, geometry::ColliderBuilder};
+use nalgebra::DMatrix;
+use rapier3d::{
+	dynamics::{
+		GenericJoint, JointAxesMask, JointLimits, JointMotor, MotorModel, RigidBodyBuilder,
+	},
+	geometry::{ColliderBuilder, ColliderHandle, Group, InteractionGroups, SharedShape},
+	parry::shape::Shape,
+};
 
 use crate::{
 	client::{Client, ClientId},
 	physics::PhysicsState,
-	server::InstanceId,
+	property::Properties,
+	rpc::RequestId,
+	server::{
+		room::{RoomId, RoomState},
+		DisconnectReason, InstanceId, JointId,
+	},
+	snapshot,
+	transport::Delivery,
 	Body, Instance, InstanceBuilder,
 };
 
@@ -15,6 +29,9 @@ use crate::{
 pub enum Error {
 	Io(io::Error),
 	Bitcode(bitcode::Error),
+	/// The client has sent more packets than it is allowed to, so the rest
+	/// are left unread for now.
+	RateLimited,
 }
 
 impl From<io::Error> for Error {
@@ -43,18 +60,177 @@ pub enum Packet<M> {
 		id: InstanceId,
 		delta: UpdateInstance,
 	},
+	/// The transforms of instances that have changed, sent by the server in place of
+	/// [`Packet::UpdateInstance`] when only the position and rotation have changed.
+	///
+	/// Entries are relative to the `baseline` snapshot, which is the latest one
+	/// acknowledged by the client with [`Packet::AckSnapshot`]. These are handled
+	/// by the connection itself, and reach the [`Context`](crate::Context) as
+	/// [`Packet::UpdateInstance`].
+	Snapshot {
+		sequence: u32,
+		baseline: Option<u32>,
+		entries: Vec<snapshot::Entry>,
+	},
+	/// Acknowledges that a [`Packet::Snapshot`] has been received.
+	AckSnapshot { sequence: u32 },
+	/// A joint has been created between two instances.
+	CreateJoint { options: CreateJoint, id: JointId },
+	/// A joint has been deleted.
+	DeleteJoint { id: JointId },
+	/// The limits, motors or frames of a joint have been updated.
+	UpdateJoint { id: JointId, data: JointData },
+	/// The gravity of the world has changed. This can only be sent by the server.
+	SetGravity { gravity: Vec3 },
 	/// A new client has connected.
-	CreateClient { instance_id: InstanceId },
+	///
+	/// The identity attached by [`App::authenticate`](crate::App::authenticate) is only
+	/// sent to the server's own context, and is `None` for everyone else.
+	CreateClient {
+		instance_id: InstanceId,
+		identity: Option<String>,
+	},
 	/// The first packet sent to a client, containing its own client id as the receiver
 	/// of the wrapped [`TrustedPacket`], and its own instance id.
 	Connected { instance_id: InstanceId },
 	/// A client has disconnected.
-	DeleteClient,
+	///
+	/// Clients send this to leave the game, in which case the reason is ignored.
+	DeleteClient { reason: DisconnectReason },
+	/// A heartbeat, which must be answered with a [`Packet::Pong`]. These are handled
+	/// by the connection itself and never reach the [`Context`](crate::Context).
+	///
+	/// `time` is the sender's estimate of the server clock, in microseconds.
+	Ping { sequence: u32, time: u64 },
+	/// The reply to a [`Packet::Ping`].
+	Pong { sequence: u32 },
 	/// A custom message (application-defined)
 	Custom(M),
+	/// A custom message sent to specific recipients. The server delivers it as a
+	/// [`Packet::Custom`], so this is never received by a [`Context`](crate::Context).
+	///
+	/// Clients can only send messages to [`Recipients::Server`].
+	Message { to: Recipients, message: M },
+	/// A request made with [`Context::request`](crate::Context::request), sent to `to`.
+	Request {
+		to: ClientId,
+		id: RequestId,
+		message: M,
+	},
+	/// The response to a [`Packet::Request`], sent back to the client that made it.
+	Response {
+		to: ClientId,
+		id: RequestId,
+		message: M,
+	},
+	/// The input of a client for a single tick, encoded from [`App::Input`](crate::App::Input).
+	/// This can only be sent by clients, to the server.
+	Input { tick: u32, input: Vec<u8> },
+	/// The state of a client's player instance after the server applied its input for `tick`,
+	/// sent back to that client to correct its prediction.
+	InputAck {
+		to: ClientId,
+		tick: u32,
+		position: Vec3,
+		rotation: Quat,
+	},
+	/// Moves a client into a room. This can only be sent by the server.
+	JoinRoom { client_id: ClientId, room: RoomId },
+	/// Changes the state of a room. This can only be sent by the server.
+	SetRoomState { room: RoomId, state: RoomState },
+	/// The room of the receiving client and its state, sent when it joins a room
+	/// or the state of its room changes.
+	Room { room: RoomId, state: RoomState },
+	/// The owner of an instance has changed. This can only be sent by the server.
+	SetOwner { id: InstanceId, owner: ClientId },
+	/// Asks the server to give the ownership of an instance to `owner`, which is
+	/// approved with [`App::approve_ownership`](crate::App::approve_ownership).
+	RequestOwnership { id: InstanceId, owner: ClientId },
+	/// A replicated property of an instance has changed, set with
+	/// [`Context::set_property`](crate::Context::set_property). The value is encoded
+	/// with [`bitcode`].
+	SetProperty {
+		id: InstanceId,
+		key: String,
+		value: Vec<u8>,
+	},
+	/// The id assigned by the server to an instance created by the receiving client,
+	/// which sent its own `local_id` in the [`Packet::CreateInstance`], or `None` if the
+	/// server rejected it. This can only be sent by the server, before the instance is
+	/// created for everyone else.
+	Spawned {
+		local_id: InstanceId,
+		id: Option<InstanceId>,
+	},
+}
+
+/// The recipients of a [`Packet::Message`].
+#[derive(Debug, Clone, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub enum Recipients {
+	/// Every client.
+	All,
+	/// A single client.
+	Client(ClientId),
+	/// A set of clients.
+	Clients(Vec<ClientId>),
+	/// Every client except one, such as the sender.
+	AllExcept(ClientId),
+	/// Only the server, which does not forward the message to anyone else.
+	Server,
+	/// Every client in a room. This can only be used by the server.
+	Room(RoomId),
+}
+
+impl Recipients {
+	/// Returns `true` if the message should be sent to the client.
+	///
+	/// This is always `false` for [`Recipients::Room`], which the server resolves to
+	/// the clients in the room.
+	///
+	/// # Examples
+	///
+	/// ```rust
+	/// use ira::{client::ClientId, packet::Recipients};
+	///
+	/// let client = ClientId::SERVER.next();
+	///
+	/// assert!(Recipients::All.contains(client));
+	/// assert!(!Recipients::AllExcept(client).contains(client));
+	/// assert!(Recipients::Clients(vec![client]).contains(client));
+	/// assert!(!Recipients::Server.contains(client));
+	/// ```
+	#[must_use]
+	pub fn contains(&self, client_id: ClientId) -> bool {
+		match self {
+			Self::All => true,
+			Self::Client(id) => *id == client_id,
+			Self::Clients(ids) => ids.contains(&client_id),
+			Self::AllExcept(id) => *id != client_id,
+			Self::Server | Self::Room(..) => false,
+		}
+	}
 }
 
 impl<M> Packet<M> {
+	/// Returns how the packet should be delivered.
+	///
+	/// Instance updates are sent frequently and superseded by the next one,
+	/// so they are sent unreliably, unless they change more than the transform of
+	/// the instance. Everything else is sent reliably.
+	#[must_use]
+	pub fn delivery(&self) -> Delivery {
+		match self {
+			Self::UpdateInstance { delta, .. } if !delta.is_transform_only() => Delivery::Reliable,
+			Self::UpdateInstance { .. }
+			| Self::Snapshot { .. }
+			| Self::AckSnapshot { .. }
+			| Self::InputAck { .. }
+			| Self::Ping { .. }
+			| Self::Pong { .. } => Delivery::Unreliable,
+			_ => Delivery::Reliable,
+		}
+	}
+
 	/// Creates a new packet with custom message.
 	pub fn new(message: M) -> Self {
 		Self::Custom(message)
@@ -80,43 +256,52 @@ impl<M> Packet<M> {
 		read(client)
 	}
 
-	/// Writes a packet to multiple writers.
+	/// Decodes a packet from the data of a frame.
+	///
+	/// Arbitrary data never causes a panic, so this is safe to call on data
+	/// received from untrusted clients.
+	///
+	/// # Errors
+	///
+	/// See [`bitcode::Error`] for more information.
+	pub fn decode(data: &[u8]) -> Result<Self, Error>
+	where
+		M: bitcode::DecodeOwned,
+	{
+		Ok(bitcode::decode(data)?)
+	}
+
+	/// Writes a packet to multiple clients.
 	///
 	/// # Errors
 	///
 	/// See [`bitcode::Error`] and [`io::Error`] for more information.
-	pub fn write_iter<'w, W: Write + 'w>(
+	pub fn write_iter<'w>(
 		&self,
-		writer: impl IntoIterator<Item = &'w mut W>,
+		clients: impl IntoIterator<Item = &'w mut Client>,
 	) -> io::Result<()>
 	where
 		M: bitcode::Encode,
 	{
 		let data = bitcode::encode(self);
-		let len = (data.len() as u32).to_le_bytes();
 
-		for writer in writer {
-			writer.write_all(&len)?;
-			writer.write_all(&data)?;
+		for client in clients {
+			client.send(&data, self.delivery())?;
 		}
 
 		Ok(())
 	}
 
-	/// Writes a packet to a writer.
+	/// Writes a packet to a client.
 	///
 	/// # Errors
 	///
 	/// See [`bitcode::Error`] and [`io::Error`] for more information.
-	pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()>
+	pub fn write(&self, client: &mut Client) -> io::Result<()>
 	where
 		M: bitcode::Encode,
 	{
-		let data = bitcode::encode(self);
-		let len = (data.len() as u32).to_le_bytes();
-
-		writer.write_all(&len)?;
-		writer.write_all(&data)
+		client.send(&bitcode::encode(self), self.delivery())
 	}
 }
 
@@ -140,40 +325,37 @@ impl<M> TrustedPacket<M> {
 		read(client)
 	}
 
-	/// Writes a packet to multiple writers.
+	/// Writes a packet to multiple clients.
 	///
 	/// # Errors
 	///
 	/// See [`bitcode::Error`] and [`io::Error`] for more information.
-	pub fn write_iter<'w>(&self, writer: impl IntoIterator<Item = &'w mut Client>) -> io::Result<()>
+	pub fn write_iter<'w>(
+		&self,
+		clients: impl IntoIterator<Item = &'w mut Client>,
+	) -> io::Result<()>
 	where
 		M: bitcode::Encode,
 	{
 		let data = bitcode::encode(self);
-		let len = (data.len() as u32).to_le_bytes();
 
-		for writer in writer {
-			writer.write_all(&len)?;
-			writer.write_all(&data)?;
+		for client in clients {
+			client.send(&data, self.inner.delivery())?;
 		}
 
 		Ok(())
 	}
 
-	/// Writes a packet to a writer.
+	/// Writes a packet to a client.
 	///
 	/// # Errors
 	///
 	/// See [`bitcode::Error`] and [`io::Error`] for more information.
-	pub fn write(&self, writer: &mut Client) -> io::Result<()>
+	pub fn write(&self, client: &mut Client) -> io::Result<()>
 	where
 		M: bitcode::Encode,
 	{
-		let data = bitcode::encode(self);
-		let len = (data.len() as u32).to_le_bytes();
-
-		writer.write_all(&len)?;
-		writer.write_all(&data)
+		client.send(&bitcode::encode(self), self.inner.delivery())
 	}
 }
 
@@ -187,7 +369,7 @@ where
 }
 
 /// A packet for creating a new collider.
-#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
+#[derive(Debug, Clone, bitcode::Encode, bitcode::Decode)]
 pub enum CreateCollider {
 	Cuboid {
 		half_extents: Vec3,
@@ -208,9 +390,61 @@ pub enum CreateCollider {
 		half_height: f32,
 		radius: f32,
 	},
+	ConvexHull {
+		points: Vec<Vec3>,
+	},
+	TriMesh {
+		vertices: Vec<Vec3>,
+		indices: Vec<[u32; 3]>,
+	},
+	HeightField {
+		/// The heights of the grid, in column-major order.
+		heights: Vec<f32>,
+		rows: u32,
+		scale: Vec3,
+	},
+	/// A compound of convex parts, such as the result of a convex decomposition.
+	Compound {
+		parts: Vec<CompoundPart>,
+	},
+}
+
+/// A convex part of a [`CreateCollider::Compound`] collider.
+#[derive(Debug, Clone, bitcode::Encode, bitcode::Decode)]
+pub struct CompoundPart {
+	pub position: Vec3,
+	pub rotation: Quat,
+	pub points: Vec<Vec3>,
+}
+
+/// Returns points whose convex hull approximates the shape.
+fn convex_points(shape: &dyn Shape) -> Vec<Vec3> {
+	if let Some(polyhedron) = shape.as_convex_polyhedron() {
+		return polyhedron.points().iter().map(|&p| p.into()).collect();
+	}
+
+	let vertices = if let Some(ball) = shape.as_ball() {
+		ball.to_trimesh(8, 8).0
+	} else if let Some(cuboid) = shape.as_cuboid() {
+		cuboid.to_trimesh().0
+	} else if let Some(capsule) = shape.as_capsule() {
+		capsule.to_trimesh(8, 4).0
+	} else if let Some(cylinder) = shape.as_cylinder() {
+		cylinder.to_trimesh(16).0
+	} else if let Some(cone) = shape.as_cone() {
+		cone.to_trimesh(16).0
+	} else {
+		shape.compute_local_aabb().vertices().to_vec()
+	};
+
+	vertices.into_iter().map(Vec3::from).collect()
 }
 
 impl CreateCollider {
+	/// Converts the shape of a collider builder into its network representation.
+	///
+	/// Shapes without a dedicated representation (such as segments or triangles)
+	/// are approximated by their convex hull.
 	#[must_use]
 	pub fn from_builder(builder: &ColliderBuilder) -> Self {
 		let ball = builder.shape.as_ball();
@@ -257,10 +491,184 @@ impl CreateCollider {
 			};
 		}
 
-		todo!()
+		let trimesh = builder.shape.as_trimesh();
+
+		if let Some(trimesh) = trimesh {
+			return Self::TriMesh {
+				vertices: trimesh.vertices().iter().map(|&v| v.into()).collect(),
+				indices: trimesh.indices().to_vec(),
+			};
+		}
+
+		let heightfield = builder.shape.as_heightfield();
+
+		if let Some(heightfield) = heightfield {
+			return Self::HeightField {
+				heights: heightfield.heights().as_slice().to_vec(),
+				rows: heightfield.heights().nrows() as u32,
+				scale: (*heightfield.scale()).into(),
+			};
+		}
+
+		let compound = builder.shape.as_compound();
+
+		if let Some(compound) = compound {
+			return Self::Compound {
+				parts: compound
+					.shapes()
+					.iter()
+					.map(|(isometry, shape)| {
+						let (position, rotation) = (*isometry).into();
+
+						CompoundPart {
+							position,
+							rotation,
+							points: convex_points(&**shape),
+						}
+					})
+					.collect(),
+			};
+		}
+
+		Self::ConvexHull {
+			points: convex_points(&*builder.shape),
+		}
+	}
+
+	/// Checks that the shape can be built with [`Self::to_builder`], which panics on
+	/// invalid shapes such as a triangle mesh indexing past its vertices. The server
+	/// checks the shapes received from clients before anything else.
+	///
+	/// # Errors
+	///
+	/// Returns the reason the shape is invalid.
+	pub fn validate(&self) -> Result<(), String> {
+		let finite = |points: &[Vec3]| points.iter().all(|p| p.is_finite());
+		// degenerate shapes make parry produce NaNs, so sizes must be positive
+		let positive = |size: f32| size.is_finite() && size > 0.0;
+		let valid = match self {
+			Self::Cuboid { half_extents } => half_extents.to_array().into_iter().all(positive),
+			Self::Sphere { radius } => positive(*radius),
+			Self::Capsule {
+				segment_a,
+				segment_b,
+				radius,
+			} => segment_a.is_finite() && segment_b.is_finite() && positive(*radius),
+			Self::Cylinder {
+				half_height,
+				radius,
+			}
+			| Self::Cone {
+				half_height,
+				radius,
+			} => positive(*half_height) && positive(*radius),
+			Self::ConvexHull { points } => finite(points),
+			Self::TriMesh { vertices, indices } => {
+				finite(vertices)
+					&& !indices.is_empty()
+					&& indices
+						.iter()
+						.flatten()
+						.all(|&i| (i as usize) < vertices.len())
+			}
+			Self::HeightField {
+				heights,
+				rows,
+				scale,
+			} => {
+				let rows = *rows as usize;
+
+				// parry needs at least 2 rows and 2 columns
+				heights.iter().all(|h| h.is_finite())
+					&& scale.is_finite()
+					&& rows >= 2 && heights.len() % rows == 0
+					&& heights.len() / rows >= 2
+			}
+			Self::Compound { parts } => {
+				!parts.is_empty()
+					&& parts.iter().all(|part| {
+						part.position.is_finite()
+							&& part.rotation.is_finite()
+							&& finite(&part.points)
+					})
+			}
+		};
+
+		if valid {
+			Ok(())
+		} else {
+			Err(format!("invalid collider shape {self:?}"))
+		}
+	}
+
+	/// Converts the network representation back into a collider builder.
+	///
+	/// # Panics
+	///
+	/// Panics if the shape is invalid, see [`Self::validate`].
+	pub fn to_builder(&self) -> ColliderBuilder {
+		match self {
+			Self::Cuboid { half_extents } => {
+				ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
+			}
+			Self::Sphere { radius } => ColliderBuilder::ball(*radius),
+			Self::Capsule {
+				segment_a,
+				segment_b,
+				radius,
+			} => ColliderBuilder::capsule_from_endpoints(
+				(*segment_a).into(),
+				(*segment_b).into(),
+				*radius,
+			),
+			Self::Cylinder {
+				half_height,
+				radius,
+			} => ColliderBuilder::cylinder(*half_height, *radius),
+			Self::Cone {
+				half_height,
+				radius,
+			} => ColliderBuilder::cone(*half_height, *radius),
+			Self::ConvexHull { points } => ColliderBuilder::new(convex_hull(points)),
+			Self::TriMesh { vertices, indices } => ColliderBuilder::trimesh(
+				vertices.iter().map(|&v| v.into()).collect(),
+				indices.clone(),
+			),
+			Self::HeightField {
+				heights,
+				rows,
+				scale,
+			} => {
+				let rows = (*rows as usize).max(1);
+
+				ColliderBuilder::heightfield(
+					DMatrix::from_column_slice(rows, heights.len() / rows, heights),
+					(*scale).into(),
+				)
+			}
+			Self::Compound { parts } => ColliderBuilder::compound(
+				parts
+					.iter()
+					.map(|part| {
+						(
+							(part.position, part.rotation).into(),
+							convex_hull(&part.points),
+						)
+					})
+					.collect(),
+			),
+		}
 	}
 }
 
+/// Computes the convex hull of the points, falling back to a point-sized ball
+/// if the points are degenerate.
+fn convex_hull(points: &[Vec3]) -> SharedShape {
+	let points = points.iter().map(|&p| p.into()).collect::<Vec<_>>();
+
+	SharedShape::convex_hull(&points).unwrap_or_else(|| SharedShape::ball(0.0))
+}
+
 #[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
 pub enum CreateBody {
 	Static,
@@ -280,6 +688,34 @@ pub struct CreateInstance {
 	pub scale: Vec3,
 	pub body: CreateBody,
 	pub collider: Option<CreateCollider>,
+	pub collision_groups: CreateGroups,
+	pub solver_groups: CreateGroups,
+	pub properties: Properties,
+}
+
+/// The network representation of [`InteractionGroups`], as raw layer bits.
+#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
+pub struct CreateGroups {
+	pub memberships: u32,
+	pub filter: u32,
+}
+
+impl From<InteractionGroups> for CreateGroups {
+	fn from(groups: InteractionGroups) -> Self {
+		Self {
+			memberships: groups.memberships.bits(),
+			filter: groups.filter.bits(),
+		}
+	}
+}
+
+impl From<CreateGroups> for InteractionGroups {
+	fn from(groups: CreateGroups) -> Self {
+		Self::new(
+			Group::from_bits_retain(groups.memberships),
+			Group::from_bits_retain(groups.filter),
+		)
+	}
 }
 
 #[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
@@ -317,6 +753,7 @@ impl From<rapier3d::dynamics::RigidBodyType> for RigidBodyType {
 }
 
 impl CreateInstance {
+	/// Applies an update, so that clients that connect later receive the latest state.
 	pub fn apply(&mut self, delta: &UpdateInstance) {
 		self.position = delta.position;
 		self.rotation = delta.rotation;
@@ -325,61 +762,65 @@ impl CreateInstance {
 			self.scale = scale;
 		}
 
-		if let Some(body) = &delta.body {
-			match (&mut self.body, body) {
-				(CreateBody::Static, UpdateBody::Static { .. }) => {
-					self.body = CreateBody::Static;
-				}
-				(
-					CreateBody::Rigid {
-						velocity,
-						angular_velocity,
-						..
-					},
-					UpdateBody::Rigid {
-						velocity: new_velocity,
-						angular_velocity: new_angular_velocity,
-					},
-				) => {
-					*velocity = *new_velocity;
-					*angular_velocity = *new_angular_velocity;
-				}
-				_ => todo!(),
+		if let Some(model_id) = delta.model_id {
+			self.model_id = model_id;
+		}
+
+		if let Some(collider) = &delta.collider {
+			self.collider = Some(collider.clone());
+		}
+
+		match (&mut self.body, delta.body) {
+			(body, Some(UpdateBody::Static)) => *body = CreateBody::Static,
+			(
+				body,
+				Some(UpdateBody::Rigid {
+					kind,
+					velocity,
+					angular_velocity,
+				}),
+			) => {
+				*body = CreateBody::Rigid {
+					kind,
+					velocity,
+					angular_velocity,
+				};
 			}
+			(
+				CreateBody::Rigid {
+					velocity,
+					angular_velocity,
+					..
+				},
+				Some(UpdateBody::Velocity {
+					velocity: new_velocity,
+					angular_velocity: new_angular_velocity,
+				}),
+			) => {
+				*velocity = new_velocity;
+				*angular_velocity = new_angular_velocity;
+			}
+			// static instances have no velocity
+			(_, None) | (CreateBody::Static, Some(UpdateBody::Velocity { .. })) => {}
 		}
 	}
 
 	pub fn to_builder(&self) -> InstanceBuilder {
-		let instance = Instance::builder()
+		let collision_groups = InteractionGroups::from(self.collision_groups);
+		let solver_groups = InteractionGroups::from(self.solver_groups);
+
+		let mut instance = Instance::builder()
 			.position(self.position)
 			.rotation(self.rotation)
-			.scale(self.scale);
+			.scale(self.scale)
+			.collision_groups(collision_groups.memberships, collision_groups.filter)
+			.solver_groups(solver_groups.memberships, solver_groups.filter);
 
-		let instance = match self.collider {
+		instance.properties = self.properties.clone();
+
+		let instance = match &self.collider {
 			None => instance,
-			Some(collider) => instance.collider(match collider {
-				CreateCollider::Cuboid { half_extents } => {
-					ColliderBuilder::cuboid(half_extents.x, half_extents.y, half_extents.z)
-				}
-				CreateCollider::Sphere { radius } => ColliderBuilder::ball(radius),
-				CreateCollider::Capsule {
-					segment_a,
-					segment_b,
-					radius,
-				} => ColliderBuilder::capsule_from_endpoints(
-					segment_a.into(),
-					segment_b.into(),
-					radius,
-				),
-				CreateCollider::Cylinder {
-					half_height,
-					radius,
-				} => ColliderBuilder::cylinder(half_height, radius),
-				CreateCollider::Cone {
-					half_height,
-					radius,
-				} => ColliderBuilder::cone(half_height, radius),
-			}),
+			Some(collider) => instance.collider(collider.to_builder()),
 		};
 
 		match self.body {
@@ -411,6 +852,16 @@ impl CreateInstance {
 
 		let collider = builder.collider.as_ref().map(CreateCollider::from_builder);
 
+		// groups set on the instance take precedence over the ones on its collider
+		let collision_groups = builder
+			.collision_groups
+			.or_else(|| builder.collider.as_ref().map(|c| c.collision_groups))
+			.unwrap_or_default();
+		let solver_groups = builder
+			.solver_groups
+			.or_else(|| builder.collider.as_ref().map(|c| c.solver_groups))
+			.unwrap_or_default();
+
 		Self {
 			model_id,
 			position,
@@ -418,17 +869,132 @@ impl CreateInstance {
 			scale,
 			body,
 			collider,
+			collision_groups: collision_groups.into(),
+			solver_groups: solver_groups.into(),
+			properties: builder.properties.clone(),
 		}
 	}
 }
 
-#[derive(Debug, bitcode::Encode, bitcode::Decode)]
+/// A packet for creating a new joint between two instances.
+///
+/// The joint only exists while both instances exist, and is removed
+/// alongside the first of them to be deleted.
+#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
+pub struct CreateJoint {
+	pub instance_a: InstanceId,
+	pub instance_b: InstanceId,
+	pub data: JointData,
+}
+
+/// A motor driving one of the degrees of freedom of a joint.
+#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
+pub struct MotorData {
+	pub target_velocity: f32,
+	pub target_position: f32,
+	pub stiffness: f32,
+	pub damping: f32,
+	pub max_force: f32,
+	/// Whether the motor uses [`MotorModel::ForceBased`] instead of
+	/// [`MotorModel::AccelerationBased`].
+	pub force_based: bool,
+}
+
+/// The network representation of a [`GenericJoint`].
+///
+/// Every joint builder provided by rapier (fixed, revolute, prismatic,
+/// spherical, rope, etc.) converts into a [`GenericJoint`], so this covers
+/// all of them, including their limits and motors.
+#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
+pub struct JointData {
+	/// The joint's frame, expressed in the first instance's local space.
+	pub frame_a: (Vec3, Quat),
+	/// The joint's frame, expressed in the second instance's local space.
+	pub frame_b: (Vec3, Quat),
+
+	pub locked_axes: u8,
+	pub limit_axes: u8,
+	pub motor_axes: u8,
+	pub coupled_axes: u8,
+
+	/// The `(min, max)` limits along each degree of freedom.
+	pub limits: [(f32, f32); 6],
+	pub motors: [MotorData; 6],
+
+	pub contacts_enabled: bool,
+}
+
+impl JointData {
+	#[must_use]
+	pub fn from_joint(joint: &GenericJoint) -> Self {
+		Self {
+			frame_a: joint.local_frame1.into(),
+			frame_b: joint.local_frame2.into(),
+			locked_axes: joint.locked_axes.bits(),
+			limit_axes: joint.limit_axes.bits(),
+			motor_axes: joint.motor_axes.bits(),
+			coupled_axes: joint.coupled_axes.bits(),
+			limits: joint.limits.map(|limit| (limit.min, limit.max)),
+			motors: joint.motors.map(|motor| MotorData {
+				target_velocity: motor.target_vel,
+				target_position: motor.target_pos,
+				stiffness: motor.stiffness,
+				damping: motor.damping,
+				max_force: motor.max_force,
+				force_based: motor.model == MotorModel::ForceBased,
+			}),
+			contacts_enabled: joint.contacts_enabled,
+		}
+	}
+
+	#[must_use]
+	pub fn to_joint(&self) -> GenericJoint {
+		let mut joint = GenericJoint::new(JointAxesMask::from_bits_truncate(self.locked_axes));
+
+		joint.local_frame1 = self.frame_a.into();
+		joint.local_frame2 = self.frame_b.into();
+		joint.limit_axes = JointAxesMask::from_bits_truncate(self.limit_axes);
+		joint.motor_axes = JointAxesMask::from_bits_truncate(self.motor_axes);
+		joint.coupled_axes = JointAxesMask::from_bits_truncate(self.coupled_axes);
+		joint.limits = self.limits.map(|(min, max)| JointLimits {
+			min,
+			max,
+			impulse: 0.0,
+		});
+		joint.motors = self.motors.map(|motor| JointMotor {
+			target_vel: motor.target_velocity,
+			target_pos: motor.target_position,
+			stiffness: motor.stiffness,
+			damping: motor.damping,
+			max_force: motor.max_force,
+			impulse: 0.0,
+			model: if motor.force_based {
+				MotorModel::ForceBased
+			} else {
+				MotorModel::AccelerationBased
+			},
+		});
+		joint.contacts_enabled = self.contacts_enabled;
+
+		joint
+	}
+}
+
+/// A change to the body of an instance.
+#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
 pub enum UpdateBody {
-	Static {
-		position: Vec3,
-		rotation: Quat,
-	},
+	/// The instance has become static, removing its rigidbody.
+	Static,
+	/// The instance has become a rigidbody of `kind`, or the kind of its rigidbody
+	/// has changed.
 	Rigid {
+		kind: RigidBodyType,
+		velocity: Vec3,
+		angular_velocity: Vec3,
+	},
+	/// The velocities of the rigidbody of the instance, which change continuously.
+	/// This is ignored by static instances.
+	Velocity {
 		velocity: Vec3,
 		angular_velocity: Vec3,
 	},
@@ -444,18 +1010,65 @@ pub struct UpdateInstance {
 	// These fields are optional, and are only included if they change.
 	pub scale: Option<Vec3>,
 	pub body: Option<UpdateBody>,
+	/// The model the instance is rendered with.
+	pub model_id: Option<u32>,
+	/// The collider replacing the current one of the instance, which keeps its
+	/// collision and solver groups.
+	pub collider: Option<CreateCollider>,
 }
 
 impl UpdateInstance {
+	/// Creates an update that only moves the instance.
+	#[must_use]
+	pub fn transform(position: Vec3, rotation: Quat) -> Self {
+		Self {
+			position,
+			rotation,
+			scale: None,
+			body: None,
+			model_id: None,
+			collider: None,
+		}
+	}
+
+	/// Returns `true` if only the position, rotation and velocities are updated, in which
+	/// case the update can be sent in a [`Packet::Snapshot`].
+	///
+	/// Other changes are rare, so they are sent reliably instead.
+	#[must_use]
+	pub fn is_transform_only(&self) -> bool {
+		self.scale.is_none()
+			&& self.model_id.is_none()
+			&& self.collider.is_none()
+			&& matches!(self.body, None | Some(UpdateBody::Velocity { .. }))
+	}
+
+	/// Applies the update to an instance.
+	///
+	/// Turning a static instance into a rigidbody (and back) and swapping its model
+	/// need the rest of the [`Context`](crate::Context), so they are only applied by
+	/// [`Context::update_instance_local`](crate::Context::update_instance_local).
 	pub fn apply(&self, physics: &mut PhysicsState, instance: &mut Instance) {
 		if let Some(scale) = self.scale {
 			instance.scale = scale;
 		}
 
+		if let Some(collider) = &self.collider {
+			instance.collider = Some(self.replace_collider(physics, instance, collider));
+		}
+
 		match &mut instance.body {
 			Body::Static { position, rotation } => {
 				*position = self.position;
 				*rotation = self.rotation;
+
+				// static colliders have no rigidbody to follow
+				if let Some(collider) = instance
+					.collider
+					.and_then(|collider| physics.colliders.get_mut(collider))
+				{
+					collider.set_position((self.position, self.rotation).into());
+				}
 			}
 			Body::Rigid(body) => {
 				let Some(rigidbody) = physics.rigid_bodies.get_mut(*body) else {
@@ -464,15 +1077,291 @@ impl UpdateInstance {
 
 				rigidbody.set_position((self.position, self.rotation).into(), true);
 
-				if let Some(UpdateBody::Rigid {
-					velocity,
-					angular_velocity,
-				}) = self.body
-				{
-					rigidbody.set_linvel(velocity.into(), true);
-					rigidbody.set_angvel(angular_velocity.into(), true);
+				let (velocity, angular_velocity) = match self.body {
+					Some(UpdateBody::Rigid {
+						kind,
+						velocity,
+						angular_velocity,
+					}) => {
+						rigidbody.set_body_type(kind.into(), true);
+
+						(velocity, angular_velocity)
+					}
+					Some(UpdateBody::Velocity {
+						velocity,
+						angular_velocity,
+					}) => (velocity, angular_velocity),
+					Some(UpdateBody::Static) | None => return,
 				};
+
+				rigidbody.set_linvel(velocity.into(), true);
+				rigidbody.set_angvel(angular_velocity.into(), true);
+			}
+		}
+	}
+
+	/// Replaces the collider of an instance, keeping the groups of the previous one.
+	fn replace_collider(
+		&self,
+		physics: &mut PhysicsState,
+		instance: &Instance,
+		collider: &CreateCollider,
+	) -> ColliderHandle {
+		let previous = instance.collider.and_then(|collider| {
+			physics.colliders.remove(
+				collider,
+				&mut physics.islands,
+				&mut physics.rigid_bodies,
+				true,
+			)
+		});
+
+		let mut builder = collider.to_builder();
+
+		if let Some(previous) = previous {
+			builder = builder
+				.collision_groups(previous.collision_groups())
+				.solver_groups(previous.solver_groups());
+		}
+
+		match instance.body {
+			Body::Rigid(body) => {
+				physics
+					.colliders
+					.insert_with_parent(builder, body, &mut physics.rigid_bodies)
+			}
+			Body::Static { .. } => physics
+				.colliders
+				.insert(builder.position((self.position, self.rotation).into())),
+		}
+	}
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+	use super::*;
+	use crate::snapshot;
+
+	/// A small xorshift generator, so the fuzzed inputs are reproducible.
+	pub(crate) struct Rng(u64);
+
+	impl Rng {
+		pub(crate) fn new() -> Self {
+			Self(0x2545_f491_4f6c_dd1d)
+		}
+
+		pub(crate) fn next(&mut self) -> u64 {
+			self.0 ^= self.0 << 13;
+			self.0 ^= self.0 >> 7;
+			self.0 ^= self.0 << 17;
+			self.0
+		}
+
+		/// Returns a frame derived from `valid`, which is random bytes, a truncated
+		/// copy or a copy with a flipped bit.
+		pub(crate) fn mutate(&mut self, valid: &[u8]) -> Vec<u8> {
+			match self.next() % 3 {
+				0 => (0..self.next() % 256).map(|_| self.next() as u8).collect(),
+				1 => valid[..self.next() as usize % valid.len()].to_vec(),
+				_ => {
+					let mut data = valid.to_vec();
+					let bit = self.next() as usize % (data.len() * 8);
+
+					data[bit / 8] ^= 1 << (bit % 8);
+					data
+				}
 			}
 		}
 	}
+
+	/// Returns one collider of every shape.
+	fn colliders() -> Vec<CreateCollider> {
+		let points = vec![Vec3::ZERO, Vec3::X, Vec3::Y, Vec3::Z];
+
+		vec![
+			CreateCollider::Cuboid {
+				half_extents: Vec3::ONE,
+			},
+			CreateCollider::Sphere { radius: 1.0 },
+			CreateCollider::Capsule {
+				segment_a: Vec3::ZERO,
+				segment_b: Vec3::Y,
+				radius: 0.5,
+			},
+			CreateCollider::Cylinder {
+				half_height: 1.0,
+				radius: 0.5,
+			},
+			CreateCollider::Cone {
+				half_height: 1.0,
+				radius: 0.5,
+			},
+			CreateCollider::ConvexHull {
+				points: points.clone(),
+			},
+			CreateCollider::TriMesh {
+				vertices: points.clone(),
+				indices: vec![[0, 1, 2], [0, 2, 3]],
+			},
+			CreateCollider::HeightField {
+				heights: vec![0.0, 1.0, 2.0, 3.0, 4.0, 5.0],
+				rows: 2,
+				scale: Vec3::ONE,
+			},
+			CreateCollider::Compound {
+				parts: vec![CompoundPart {
+					position: Vec3::X,
+					rotation: Quat::IDENTITY,
+					points,
+				}],
+			},
+		]
+	}
+
+	/// Returns packets that clients send with untrusted data: instances with every
+	/// collider shape, updates, snapshots and messages.
+	pub(crate) fn samples() -> Vec<Packet<String>> {
+		let mut properties = Properties::default();
+
+		properties.set("health", &100u32);
+
+		let instances = colliders()
+			.into_iter()
+			.map(|collider| Packet::CreateInstance {
+				options: CreateInstance {
+					model_id: 0,
+					position: Vec3::new(1.0, 2.0, 3.0),
+					rotation: Quat::from_rotation_y(1.0),
+					scale: Vec3::ONE,
+					body: CreateBody::Rigid {
+						kind: RigidBodyType::Dynamic,
+						velocity: Vec3::X,
+						angular_velocity: Vec3::Y,
+					},
+					collider: Some(collider),
+					collision_groups: CreateGroups {
+						memberships: 1,
+						filter: u32::MAX,
+					},
+					solver_groups: CreateGroups {
+						memberships: 1,
+						filter: u32::MAX,
+					},
+					properties: properties.clone(),
+				},
+				id: InstanceId::new(0),
+			});
+
+		let update = Packet::UpdateInstance {
+			id: InstanceId::new(0),
+			delta: UpdateInstance {
+				model_id: Some(0),
+				collider: colliders().pop(),
+				..UpdateInstance::transform(Vec3::ONE, Quat::IDENTITY)
+			},
+		};
+
+		let snapshot = Packet::Snapshot {
+			sequence: 1,
+			baseline: Some(0),
+			entries: vec![snapshot::Entry {
+				id: InstanceId::new(0),
+				transform: snapshot::Transform::quantize(Vec3::ONE, Quat::IDENTITY, 0.01),
+			}],
+		};
+
+		let message = Packet::Message {
+			to: Recipients::Server,
+			message: String::from("hello"),
+		};
+
+		instances.chain([update, snapshot, message]).collect()
+	}
+
+	#[test]
+	fn decoding_fuzzed_packets_never_panics() {
+		let mut rng = Rng::new();
+
+		for packet in samples() {
+			let valid = bitcode::encode(&packet);
+
+			assert!(Packet::<String>::decode(&valid).is_ok(), "{packet:?}");
+
+			for _ in 0..2_000 {
+				let _ = Packet::<String>::decode(&rng.mutate(&valid));
+			}
+		}
+	}
+
+	#[test]
+	fn valid_colliders_build() {
+		for collider in colliders() {
+			assert!(collider.validate().is_ok(), "{collider:?}");
+			let _ = collider.to_builder();
+		}
+	}
+
+	#[test]
+	fn invalid_colliders_are_rejected() {
+		let colliders = [
+			CreateCollider::Sphere { radius: f32::NAN },
+			CreateCollider::Sphere { radius: 0.0 },
+			CreateCollider::Sphere { radius: -1.0 },
+			CreateCollider::Cuboid {
+				half_extents: Vec3::new(1.0, 0.0, 1.0),
+			},
+			CreateCollider::Cuboid {
+				half_extents: -Vec3::ONE,
+			},
+			CreateCollider::Capsule {
+				segment_a: Vec3::ZERO,
+				segment_b: Vec3::Y,
+				radius: 0.0,
+			},
+			CreateCollider::Cylinder {
+				half_height: 0.0,
+				radius: 0.5,
+			},
+			CreateCollider::Cylinder {
+				half_height: 1.0,
+				radius: -0.5,
+			},
+			CreateCollider::Cone {
+				half_height: -1.0,
+				radius: 0.5,
+			},
+			CreateCollider::Cone {
+				half_height: 1.0,
+				radius: 0.0,
+			},
+			CreateCollider::TriMesh {
+				vertices: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+				indices: vec![],
+			},
+			CreateCollider::TriMesh {
+				vertices: vec![Vec3::ZERO, Vec3::X, Vec3::Y],
+				indices: vec![[0, 1, 3]],
+			},
+			CreateCollider::HeightField {
+				heights: vec![0.0; 5],
+				rows: 2,
+				scale: Vec3::ONE,
+			},
+			CreateCollider::HeightField {
+				heights: vec![0.0; 2],
+				rows: 1,
+				scale: Vec3::ONE,
+			},
+			CreateCollider::HeightField {
+				heights: vec![0.0; 2],
+				rows: 2,
+				scale: Vec3::ONE,
+			},
+			CreateCollider::Compound { parts: vec![] },
+		];
+
+		for collider in colliders {
+			assert!(collider.validate().is_err(), "{collider:?}");
+		}
+	}
 }
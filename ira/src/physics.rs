@@ -1,19 +1,22 @@
-use std::ops;
+use std::{num::NonZeroUsize, ops};
 
 use glam::Vec3;
 use rapier3d::{
 	data::{Arena, Index},
 	dynamics::{
 		CCDSolver, ImpulseJointSet, IntegrationParameters, IslandManager, MultibodyJointSet,
-		RigidBodyBuilder, RigidBodyHandle, RigidBodySet,
+		RigidBodyBuilder, RigidBodyHandle, RigidBodySet, RigidBodyType,
 	},
 	geometry::{ColliderBuilder, ColliderHandle, ColliderSet, DefaultBroadPhase, NarrowPhase},
 	pipeline::PhysicsPipeline,
 };
 
+use tracing::warn;
+
 use crate::{
+	client::ClientId,
 	game::Context,
-	packet::{CreateInstance, Packet, UpdateInstance},
+	packet::{CreateInstance, Packet, UpdateBody, UpdateInstance},
 	server::InstanceId,
 	Body, GpuDrum, GpuModel, Instance, InstanceBuilder,
 };
@@ -37,9 +40,49 @@ impl BoundingBox {
 	}
 }
 
+/// Configuration for the physics world, provided by [`App::physics`](crate::App::physics).
+///
+/// The client and server should use the same configuration.
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicsConfig {
+	/// The initial gravity of the world. This can be changed at runtime with
+	/// [`Context::set_gravity`].
+	pub gravity: Vec3,
+	/// The duration of a single physics step, in seconds.
+	/// [`App::on_fixed_update`](crate::App::on_fixed_update) is called once per step.
+	pub timestep: f32,
+	/// The number of solver iterations run per step. Higher values improve the
+	/// accuracy of contacts and joints at the cost of performance.
+	pub solver_iterations: NonZeroUsize,
+	/// Whether continuous collision detection is enabled for all instances with a
+	/// dynamic rigidbody. This prevents fast-moving instances from tunneling through
+	/// thin colliders.
+	pub ccd: bool,
+	/// The maximum number of substeps taken by continuous collision detection.
+	pub max_ccd_substeps: usize,
+	/// The number of physics steps between each network update of instance positions.
+	pub ticks_per_update: u32,
+}
+
+impl Default for PhysicsConfig {
+	fn default() -> Self {
+		let integration = IntegrationParameters::default();
+
+		Self {
+			gravity: PhysicsState::GRAVITY,
+			timestep: integration.dt,
+			solver_iterations: integration.num_solver_iterations,
+			ccd: false,
+			max_ccd_substeps: integration.max_ccd_substeps,
+			ticks_per_update: PhysicsState::TICKS_PER_UPDATE,
+		}
+	}
+}
+
 pub struct PhysicsState {
 	pub pipeline: PhysicsPipeline,
 
+	pub gravity: Vec3,
 	pub integration: IntegrationParameters,
 	pub islands: IslandManager,
 	pub broad_phase: DefaultBroadPhase,
@@ -53,14 +96,37 @@ pub struct PhysicsState {
 	pub rigid_bodies: RigidBodySet,
 	pub colliders: ColliderSet,
 
+	pub(crate) ccd: bool,
+	pub(crate) ticks_per_update: u32,
 	pub(crate) steps_since_last_update: u32,
 }
 
 impl Default for PhysicsState {
 	fn default() -> Self {
+		Self::new(&PhysicsConfig::default())
+	}
+}
+
+impl PhysicsState {
+	/// The default gravity of the world.
+	pub const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
+	/// The default number of physics steps between each network update.
+	pub const TICKS_PER_UPDATE: u32 = 10;
+
+	/// Creates a new physics world with the given configuration.
+	#[must_use]
+	pub fn new(config: &PhysicsConfig) -> Self {
+		let integration = IntegrationParameters {
+			dt: config.timestep,
+			num_solver_iterations: config.solver_iterations,
+			max_ccd_substeps: config.max_ccd_substeps,
+			..IntegrationParameters::default()
+		};
+
 		Self {
 			pipeline: PhysicsPipeline::new(),
-			integration: IntegrationParameters::default(),
+			gravity: config.gravity,
+			integration,
 			islands: IslandManager::new(),
 			broad_phase: DefaultBroadPhase::new(),
 			narrow_phase: NarrowPhase::new(),
@@ -69,18 +135,15 @@ impl Default for PhysicsState {
 			ccd_solver: CCDSolver::new(),
 			rigid_bodies: RigidBodySet::new(),
 			colliders: ColliderSet::new(),
+			ccd: config.ccd,
+			ticks_per_update: config.ticks_per_update,
 			steps_since_last_update: 0,
 		}
 	}
-}
-
-impl PhysicsState {
-	pub const GRAVITY: Vec3 = Vec3::new(0.0, -9.81, 0.0);
-	pub const TICKS_PER_UPDATE: u32 = 10;
 
 	pub fn step(&mut self) {
 		self.pipeline.step(
-			&nalgebra::Vector3::from(Self::GRAVITY),
+			&nalgebra::Vector3::from(self.gravity),
 			&self.integration,
 			&mut self.islands,
 			&mut self.broad_phase,
@@ -101,10 +164,22 @@ impl PhysicsState {
 	/// Modifies the provided instance to include the rigid body and collider handles.
 	fn add_instance(
 		&mut self,
-		instance: InstanceBuilder,
+		mut instance: InstanceBuilder,
 		model_id: u32,
 		instance_id: u32,
 	) -> Instance {
+		instance.collider = instance.collider.map(|mut collider| {
+			if let Some(groups) = instance.collision_groups {
+				collider = collider.collision_groups(groups);
+			}
+
+			if let Some(groups) = instance.solver_groups {
+				collider = collider.solver_groups(groups);
+			}
+
+			collider
+		});
+
 		let mut new_instance = Instance {
 			scale: instance.scale,
 			collider: None,
@@ -194,7 +269,7 @@ impl<M> Context<M> {
 
 			#[cfg(feature = "server")]
 			{
-				if self.physics.steps_since_last_update < PhysicsState::TICKS_PER_UPDATE {
+				if self.physics.steps_since_last_update < self.physics.ticks_per_update {
 					continue;
 				}
 
@@ -203,24 +278,50 @@ impl<M> Context<M> {
 				};
 
 				let (position, rotation) = (*body.position()).into();
-				let _ = self.packet_tx.send(Packet::UpdateInstance {
-					id: *id,
-					delta: UpdateInstance {
-						position,
-						rotation,
-						scale: None,
-						body: None,
-					},
+				let mut delta = UpdateInstance::transform(position, rotation);
+
+				// recorded by the server for clients that connect later
+				delta.body = Some(UpdateBody::Velocity {
+					velocity: (*body.linvel()).into(),
+					angular_velocity: (*body.angvel()).into(),
 				});
+
+				let _ = self
+					.packet_tx
+					.send(Packet::UpdateInstance { id: *id, delta });
 			}
 		}
 
 		#[cfg(feature = "server")]
-		if self.physics.steps_since_last_update >= PhysicsState::TICKS_PER_UPDATE {
+		if self.physics.steps_since_last_update >= self.physics.ticks_per_update {
 			self.physics.steps_since_last_update = 0;
 		}
 	}
 
+	/// Changes the gravity of the world, then notifies all clients.
+	///
+	/// Only the server can change the gravity, so that every client agrees on it.
+	/// Clients joining later will receive the latest gravity.
+	///
+	/// The local version of this method is [`Context::set_gravity_local`].
+	#[cfg(feature = "server")]
+	pub fn set_gravity(&mut self, gravity: Vec3) {
+		self.set_gravity_local(gravity);
+
+		let _ = self.packet_tx.send(Packet::SetGravity { gravity });
+	}
+
+	/// Changes the gravity of the world, without notifying any clients.
+	///
+	/// Sleeping rigidbodies are woken up so that they respond to the new gravity.
+	pub fn set_gravity_local(&mut self, gravity: Vec3) {
+		self.physics.gravity = gravity;
+
+		for (_, body) in self.physics.rigid_bodies.iter_mut() {
+			body.wake_up(true);
+		}
+	}
+
 	/// Adds a new rigidbody and collider to the physics world.
 	#[allow(clippy::needless_pass_by_value)]
 	pub fn add_rigidbody(
@@ -251,11 +352,23 @@ impl<M> Context<M> {
 	}
 
 	pub fn remove_instance_local(&mut self, id: InstanceId) -> Option<Instance> {
-		let instance = self.handles.remove(&id)?;
+		let handle = self.handles.remove(&id)?;
+
+		self.instance_ids.remove(&handle);
+		self.interpolation.remove(id);
+		self.owners.remove(&id);
+		self.properties.remove(&id);
+
+		self.despawn(handle)
+	}
 
-		self.instance_ids.remove(&instance);
+	/// Removes an instance from the physics world and its model, along with its joints.
+	fn despawn(&mut self, handle: InstanceHandle) -> Option<Instance> {
+		// rapier removes the joints attached to the rigidbody, so only the bookkeeping is left
+		self.joints
+			.retain(|_, joint| joint.instance_a != handle && joint.instance_b != handle);
 
-		let instance = self.instances.remove(*instance)?;
+		let instance = self.instances.remove(*handle)?;
 
 		if let Body::Rigid(handle) = instance.body {
 			self.physics.rigid_bodies.remove(
@@ -275,32 +388,235 @@ impl<M> Context<M> {
 			);
 		}
 
+		self.remove_gpu_instance(instance.model_id, instance.instance_id);
+
+		Some(instance)
+	}
+
+	/// Removes a gpu instance from its model, moving the last one in its place.
+	fn remove_gpu_instance(&mut self, model_id: u32, instance_id: u32) {
 		// swap_remove the gpu instance, then update the other instance pointing to the one at the end
-		let model = &mut self.drum.models[instance.model_id as usize];
+		let model = &mut self.drum.models[model_id as usize];
 
 		#[cfg(feature = "client")]
 		{
-			let _ = model.instances.swap_remove(instance.instance_id as usize);
+			let _ = model.instances.swap_remove(instance_id as usize);
 		}
 
 		// now, we need to know which instance owns that swapped one in O(1)
-		model.handles.swap_remove(instance.instance_id as usize);
+		model.handles.swap_remove(instance_id as usize);
 
-		// update the instance that was swapped
-		let handle = model.handles[instance.instance_id as usize];
-		if let Some(other) = self.instances.get_mut(*handle) {
-			other.instance_id = instance.instance_id;
+		// update the instance that was swapped, unless it was the last one
+		if let Some(other) = model
+			.handles
+			.get(instance_id as usize)
+			.and_then(|handle| self.instances.get_mut(**handle))
+		{
+			other.instance_id = instance_id;
 		}
+	}
 
-		Some(instance)
+	/// Returns the id of an instance shared by the server and every client, or `None` if
+	/// the instance is local or the server has not assigned its id yet.
+	#[must_use]
+	pub fn id_of(&self, instance: InstanceHandle) -> Option<InstanceId> {
+		self.instance_ids.get(&instance).copied()
+	}
+
+	/// Returns the local handle of an instance from its id, if it exists.
+	#[must_use]
+	pub fn handle_of(&self, id: InstanceId) -> Option<InstanceHandle> {
+		self.handles.get(&id).copied()
+	}
+
+	/// Gives an instance spawned by this client the id assigned by the server, or
+	/// removes it if the server rejected it.
+	pub(crate) fn receive_spawned(&mut self, local_id: InstanceId, id: Option<InstanceId>) {
+		let Some(instance) = self.spawning.remove(&local_id) else {
+			return;
+		};
+
+		let Some(id) = id else {
+			warn!(?instance, "server rejected instance, removing it");
+			self.despawn(instance);
+
+			return;
+		};
+
+		self.handles.insert(id, instance);
+		self.instance_ids.insert(instance, id);
+		self.owners
+			.insert(id, self.client_id.unwrap_or(ClientId::SERVER));
 	}
 
+	/// Changes an instance, then sends the change to everyone that can see it, such as
+	/// to resize it, swap its model, replace its collider or turn it into a rigidbody.
+	///
+	/// `update` receives an [`UpdateInstance`] with the current position and rotation
+	/// of the instance, and nothing else changed. Only the owner of the instance and
+	/// the server can change it, and clients that connect later receive its latest state.
+	///
+	/// The local version of this method is [`Context::update_instance_local`].
+	pub fn update_instance<F>(&mut self, instance: InstanceHandle, update: F)
+	where
+		F: FnOnce(&mut UpdateInstance),
+	{
+		let id = self.instance_ids.get(&instance).copied();
+		let is_server = self.client_id.is_none_or(|id| id == ClientId::SERVER);
+
+		if let Some(id) = id.filter(|id| !is_server && !self.is_owner(*id)) {
+			warn!(instance_id = ?id, "tried to update instance not owned");
+
+			return;
+		}
+
+		let Some(current) = self.instances.get(*instance) else {
+			return;
+		};
+
+		let (position, rotation) = current.body.pos_rot(&self.physics);
+		let mut delta = UpdateInstance::transform(position, rotation);
+
+		update(&mut delta);
+
+		self.update_instance_local(instance, &delta);
+
+		if let Some(id) = id {
+			let _ = self.packet_tx.send(Packet::UpdateInstance { id, delta });
+		}
+	}
+
+	/// Applies an update to an instance, without notifying any clients.
+	///
+	/// Unlike [`UpdateInstance::apply`], this also swaps the model of the instance, and
+	/// turns a static instance into a rigidbody (and back).
+	pub fn update_instance_local(&mut self, instance: InstanceHandle, delta: &UpdateInstance) {
+		if let Some(model_id) = delta.model_id {
+			self.set_model_local(instance, model_id);
+		}
+
+		if let Some(body) = delta.body {
+			self.set_body_local(instance, body);
+		}
+
+		instance.update(self, |i, p| {
+			delta.apply(p, i);
+		});
+	}
+
+	/// Moves an instance to another model, which it is rendered with from now on.
+	fn set_model_local(&mut self, instance: InstanceHandle, model_id: u32) {
+		let Some(current) = self.instances.get(*instance) else {
+			return;
+		};
+
+		if current.model_id == model_id || model_id as usize >= self.drum.models.len() {
+			return;
+		}
+
+		let (previous, instance_id) = (current.model_id, current.instance_id);
+
+		self.remove_gpu_instance(previous, instance_id);
+
+		let model = &mut self.drum.models[model_id as usize];
+		let Some(current) = self.instances.get_mut(*instance) else {
+			return;
+		};
+
+		current.model_id = model_id;
+		current.instance_id = model.handles.len() as u32;
+
+		model.add_gpu_instance(current, instance, &self.physics);
+	}
+
+	/// Adds or removes the rigidbody of an instance, when it changes between being
+	/// static and rigid. Its collider is moved over to the new body.
+	fn set_body_local(&mut self, instance: InstanceHandle, body: UpdateBody) {
+		let Some(current) = self.instances.get_mut(*instance) else {
+			return;
+		};
+
+		match (&current.body, body) {
+			(
+				Body::Static { position, rotation },
+				UpdateBody::Rigid {
+					kind,
+					velocity,
+					angular_velocity,
+				},
+			) => {
+				let kind = kind.into();
+				let ccd = self.physics.ccd && kind == RigidBodyType::Dynamic;
+				let rigidbody = RigidBodyBuilder::new(kind)
+					.position((*position, *rotation).into())
+					.linvel(velocity.into())
+					.angvel(angular_velocity.into())
+					.ccd_enabled(ccd)
+					.user_data(instance.into())
+					.build();
+				let handle = self.physics.rigid_bodies.insert(rigidbody);
+
+				if let Some(collider) = current.collider {
+					self.physics.colliders.set_parent(
+						collider,
+						Some(handle),
+						&mut self.physics.rigid_bodies,
+					);
+				}
+
+				current.body = Body::Rigid(handle);
+			}
+			(Body::Rigid(handle), UpdateBody::Static) => {
+				// the collider is detached, staying where the body was
+				let Some(rigidbody) = self.physics.rigid_bodies.remove(
+					*handle,
+					&mut self.physics.islands,
+					&mut self.physics.colliders,
+					&mut self.physics.impulse_joints,
+					&mut self.physics.multibody_joints,
+					false,
+				) else {
+					return;
+				};
+
+				let (position, rotation) = (*rigidbody.position()).into();
+
+				current.body = Body::Static { position, rotation };
+
+				// rapier removed the joints along with the rigidbody
+				self.joints.retain(|_, joint| {
+					joint.instance_a != instance && joint.instance_b != instance
+				});
+			}
+			_ => {}
+		}
+	}
+
+	/// Adds a new instance, then sends it to the server to be created for everyone else.
+	///
+	/// The instance is spawned locally right away, owned by this client. It has no
+	/// [`InstanceId`] until the server has created it and sent back a [`Packet::Spawned`],
+	/// after which [`Context::id_of`] returns the id assigned by the server.
+	///
+	/// If the server rejects the instance, such as with
+	/// [`App::validate_packet`](crate::App::validate_packet), it is removed again.
 	#[cfg(not(feature = "server"))]
-	pub fn add_instance(&mut self, model_id: u32, instance: InstanceBuilder) {
+	pub fn add_instance(&mut self, model_id: u32, instance: InstanceBuilder) -> InstanceHandle {
+		use std::sync::atomic::Ordering;
+
+		// only used to match the id assigned by the server to this instance
+		let local_id = InstanceId::new(self.next_instance_id.fetch_add(1, Ordering::SeqCst));
+
 		let _ = self.packet_tx.send(Packet::CreateInstance {
-			id: InstanceId::NONE,
+			id: local_id,
 			options: CreateInstance::from_builder(&instance, model_id),
 		});
+
+		let handle = self.add_instance_local(model_id, instance);
+
+		self.spawning.insert(local_id, handle);
+
+		handle
 	}
 
 	#[cfg(feature = "server")]
@@ -309,6 +625,11 @@ impl<M> Context<M> {
 
 		let instance_id = InstanceId::new(self.next_instance_id.fetch_add(1, Ordering::SeqCst));
 
+		if !instance.properties.is_empty() {
+			self.properties
+				.insert(instance_id, instance.properties.clone());
+		}
+
 		let _ = self.packet_tx.send(Packet::CreateInstance {
 			id: instance_id,
 			options: CreateInstance::from_builder(&instance, model_id),
@@ -345,7 +666,10 @@ impl<M> Context<M> {
 				.or_else(|| Some(model.bounds.to_cuboid(instance.scale).mass(1.0)));
 
 			instance.rigidbody = instance.rigidbody.map(|body| {
+				let ccd = body.ccd_enabled || (self.physics.ccd && body.body_type.is_dynamic());
+
 				body.position(instance.position.into())
+					.ccd_enabled(ccd)
 					.rotation((axis * angle).into())
 					.user_data(handle.into())
 			});
@@ -0,0 +1,252 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use glam::{Quat, Vec3};
+use rapier3d::dynamics::RigidBodyHandle;
+use tracing::{debug, warn};
+
+use crate::{client::ClientId, game::Context, packet::Packet, App, Body, Instance};
+
+/// The number of predicted ticks kept while waiting for the server to acknowledge them.
+const HISTORY_LEN: usize = 256;
+
+/// The number of inputs the server keeps for each client, dropping the oldest ones
+/// when a client sends them faster than they are simulated.
+const MAX_QUEUED_INPUTS: usize = 32;
+
+/// The predicted state of the local player's body after a fixed update.
+#[derive(Debug, Clone, Copy)]
+struct Predicted {
+	tick: u32,
+	position: Vec3,
+	rotation: Quat,
+}
+
+/// The state of client-side prediction, and of the inputs received by the server.
+#[derive(Default)]
+pub(crate) struct Prediction {
+	/// The body moved by the local player's inputs, set with [`Context::predict`].
+	body: Option<RigidBodyHandle>,
+	next_tick: u32,
+	/// The predicted states that have not been acknowledged yet, oldest first.
+	history: VecDeque<Predicted>,
+	/// On the server, the inputs received from each client that have not been
+	/// simulated yet, oldest first.
+	inputs: BTreeMap<ClientId, VecDeque<(u32, Vec<u8>)>>,
+	/// On the server, the inputs applied since the last physics step, which are
+	/// acknowledged once the step has run.
+	applied: Vec<(ClientId, u32)>,
+}
+
+impl Prediction {
+	/// Forgets everything predicted for the previous connection.
+	pub(crate) fn reset(&mut self) {
+		self.next_tick = 0;
+		self.history.clear();
+		self.inputs.clear();
+		self.applied.clear();
+	}
+
+	/// Forgets the inputs of a client that has left.
+	pub(crate) fn remove_client(&mut self, client_id: ClientId) {
+		self.inputs.remove(&client_id);
+	}
+}
+
+impl<M> Context<M> {
+	/// Enables client-side prediction for the local player's body, or disables it with `None`.
+	///
+	/// While enabled, [`App::input`] is called every fixed update and passed to
+	/// [`App::simulate`] right away, so the body responds without waiting for the server.
+	/// The input is also sent to the server, which applies it to the client's player
+	/// instance during one of its own fixed updates and replies with the resulting state.
+	/// If the state predicted for that tick is off by more than
+	/// [`NetworkConfig::reconciliation_threshold`](crate::server::NetworkConfig::reconciliation_threshold),
+	/// the body (and every state predicted after that tick) is shifted by the error.
+	///
+	/// Inputs are not simulated again on top of the server's state, since that would
+	/// need a physics step of its own for every tick. This keeps the movement predicted
+	/// since the acknowledged tick, so a correction is exact as long as those ticks were
+	/// not affected by the error, such as a collision the client did not predict.
+	///
+	/// On the server itself, inputs are simulated without being sent anywhere.
+	pub fn predict(&mut self, body: Option<RigidBodyHandle>) {
+		self.prediction.body = body;
+		self.prediction.history.clear();
+	}
+
+	/// Returns the position and rotation of a rigid body, if it exists.
+	fn body_state(&self, body: RigidBodyHandle) -> Option<(Vec3, Quat)> {
+		let body = self.physics.rigid_bodies.get(body)?;
+
+		Some((*body.position()).into())
+	}
+
+	/// Returns the player instance of a remote client.
+	fn player_instance(&self, client_id: ClientId) -> Option<&Instance> {
+		let instance = self.handles.get(self.clients.get(&client_id)?)?;
+
+		self.instances.get(**instance)
+	}
+
+	/// Reads the local player's input and simulates it, sending it to the server.
+	/// Returns the tick it was sent with, to be recorded after the physics step.
+	pub(crate) fn predict_input<A: App<M>>(&mut self, app: &mut A) -> Option<u32> {
+		let body = self.prediction.body?;
+		let input = app.input(self)?;
+
+		A::simulate(self, body, &input);
+
+		// the server is the authority over its own player
+		if self.client_id.is_none_or(|id| id == ClientId::SERVER) {
+			return None;
+		}
+
+		let tick = self.prediction.next_tick;
+
+		self.prediction.next_tick = tick.wrapping_add(1);
+
+		let _ = self.packet_tx.send(Packet::Input {
+			tick,
+			input: bitcode::encode(&input),
+		});
+
+		Some(tick)
+	}
+
+	/// Records the state of the local player's body after the physics step of `tick`.
+	pub(crate) fn record_prediction(&mut self, tick: Option<u32>) {
+		let Some(tick) = tick else {
+			return;
+		};
+
+		let Some((position, rotation)) = self.prediction.body.and_then(|b| self.body_state(b))
+		else {
+			return;
+		};
+
+		if self.prediction.history.len() == HISTORY_LEN {
+			self.prediction.history.pop_front();
+		}
+
+		self.prediction.history.push_back(Predicted {
+			tick,
+			position,
+			rotation,
+		});
+	}
+
+	/// Corrects the local player's body with the state computed by the server for `tick`,
+	/// by moving it (and the later predicted states) by the error of that tick.
+	#[cfg_attr(feature = "server", allow(dead_code))]
+	pub(crate) fn reconcile(&mut self, tick: u32, position: Vec3, rotation: Quat) {
+		let Some(body) = self.prediction.body else {
+			return;
+		};
+
+		let history = &mut self.prediction.history;
+		let Some(index) = history.iter().position(|p| p.tick == tick) else {
+			return;
+		};
+
+		let predicted = history.drain(..=index).next_back().unwrap();
+		let threshold = self.network.reconciliation_threshold;
+
+		if predicted.position.distance(position) <= threshold
+			&& predicted.rotation.angle_between(rotation) <= threshold
+		{
+			return;
+		}
+
+		debug!(
+			tick,
+			error = predicted.position.distance(position),
+			"reconciling with server"
+		);
+
+		// keep the movement predicted since, starting from the server's state instead
+		let offset = position - predicted.position;
+		let turn = rotation * predicted.rotation.inverse();
+
+		for later in history.iter_mut() {
+			later.position += offset;
+			later.rotation = (turn * later.rotation).normalize();
+		}
+
+		let Some(body) = self.physics.rigid_bodies.get_mut(body) else {
+			return;
+		};
+
+		let (current, current_rotation): (Vec3, Quat) = (*body.position()).into();
+
+		body.set_position(
+			(current + offset, (turn * current_rotation).normalize()).into(),
+			true,
+		);
+	}
+
+	/// Queues an input received from a client, to be simulated in a later fixed update.
+	pub(crate) fn queue_input(&mut self, client_id: ClientId, tick: u32, input: Vec<u8>) {
+		let inputs = self.prediction.inputs.entry(client_id).or_default();
+
+		if inputs.len() == MAX_QUEUED_INPUTS {
+			inputs.pop_front();
+		}
+
+		inputs.push_back((tick, input));
+	}
+
+	/// Applies the oldest queued input of each client to its player instance, so that
+	/// clients cannot move faster by sending more inputs.
+	pub(crate) fn simulate_inputs<A: App<M>>(&mut self) {
+		let queued = self
+			.prediction
+			.inputs
+			.iter_mut()
+			.filter_map(|(client_id, inputs)| Some((*client_id, inputs.pop_front()?)))
+			.collect::<Vec<_>>();
+
+		for (client_id, (tick, input)) in queued {
+			let Some(body) =
+				self.player_instance(client_id)
+					.and_then(|instance| match instance.body {
+						Body::Rigid(body) => Some(body),
+						Body::Static { .. } => None,
+					})
+			else {
+				continue;
+			};
+
+			let input = match bitcode::decode::<A::Input>(&input) {
+				Ok(input) => input,
+				Err(e) => {
+					warn!(?client_id, error = %e, "received malformed input");
+					continue;
+				}
+			};
+
+			A::simulate(self, body, &input);
+
+			self.prediction.applied.push((client_id, tick));
+		}
+	}
+
+	/// Sends the state of each client's player instance after the physics step to the
+	/// clients whose inputs were applied before it.
+	pub(crate) fn acknowledge_inputs(&mut self) {
+		for (client_id, tick) in std::mem::take(&mut self.prediction.applied) {
+			let Some((position, rotation)) = self
+				.player_instance(client_id)
+				.map(|instance| instance.body.pos_rot(&self.physics))
+			else {
+				continue;
+			};
+
+			let _ = self.packet_tx.send(Packet::InputAck {
+				to: client_id,
+				tick,
+				position,
+				rotation,
+			});
+		}
+	}
+}
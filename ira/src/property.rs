@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use tracing::warn;
+
+use crate::{client::ClientId, game::Context, packet::Packet, server::InstanceId};
+
+/// The replicated properties of an instance, such as its health, color or team.
+///
+/// Properties are keyed by name and stored encoded, so any [`bitcode`] type can be
+/// used as a value. The server keeps the properties of every instance with it, so
+/// clients that connect (or that an instance becomes relevant to) later receive the
+/// latest values along with the instance.
+///
+/// # Examples
+///
+/// ```rust
+/// use ira::property::Properties;
+///
+/// let mut properties = Properties::default();
+///
+/// assert!(properties.set("health", &100u32));
+/// assert!(!properties.set("health", &100u32));
+/// assert_eq!(properties.get::<u32>("health"), Some(100));
+/// assert_eq!(properties.get::<u32>("team"), None);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub struct Properties(BTreeMap<String, Vec<u8>>);
+
+impl Properties {
+	/// Returns the value of a property, or `None` if it is not set or was set
+	/// with a different type.
+	#[must_use]
+	pub fn get<T: bitcode::DecodeOwned>(&self, key: &str) -> Option<T> {
+		bitcode::decode(self.0.get(key)?).ok()
+	}
+
+	/// Sets the value of a property, returning `true` if it has changed.
+	pub fn set<T: bitcode::Encode + ?Sized>(&mut self, key: &str, value: &T) -> bool {
+		self.set_encoded(key, bitcode::encode(value))
+	}
+
+	/// Returns `true` if no properties are set.
+	#[must_use]
+	pub fn is_empty(&self) -> bool {
+		self.0.is_empty()
+	}
+
+	/// Sets the encoded value of a property, returning `true` if it has changed.
+	pub(crate) fn set_encoded(&mut self, key: &str, value: Vec<u8>) -> bool {
+		if self.0.get(key) == Some(&value) {
+			return false;
+		}
+
+		self.0.insert(key.to_string(), value);
+
+		true
+	}
+}
+
+impl<M> Context<M> {
+	/// Sets a replicated property of an instance. Only the owner of the instance and
+	/// the server can set its properties.
+	///
+	/// Nothing is sent if the value has not changed. Otherwise, everyone that can see
+	/// the instance receives a [`Packet::SetProperty`] with the new value.
+	pub fn set_property<T: bitcode::Encode + ?Sized>(
+		&mut self,
+		id: InstanceId,
+		key: &str,
+		value: &T,
+	) {
+		let is_server = self.client_id.is_none_or(|id| id == ClientId::SERVER);
+
+		if !is_server && !self.is_owner(id) {
+			warn!(instance_id = ?id, key, "tried to set property of instance not owned");
+
+			return;
+		}
+
+		let value = bitcode::encode(value);
+
+		if !self
+			.properties
+			.entry(id)
+			.or_default()
+			.set_encoded(key, value.clone())
+		{
+			return;
+		}
+
+		let _ = self.packet_tx.send(Packet::SetProperty {
+			id,
+			key: key.to_string(),
+			value,
+		});
+	}
+
+	/// Stores a property received from the server.
+	pub(crate) fn receive_property(&mut self, id: InstanceId, key: &str, value: &[u8]) {
+		self.properties
+			.entry(id)
+			.or_default()
+			.set_encoded(key, value.to_vec());
+	}
+
+	/// Returns the value of a replicated property of an instance, or `None` if it is
+	/// not set or was set with a different type.
+	#[must_use]
+	pub fn property<T: bitcode::DecodeOwned>(&self, id: InstanceId, key: &str) -> Option<T> {
+		self.properties.get(&id)?.get(key)
+	}
+
+	/// Returns the replicated properties of an instance, if any are set.
+	#[must_use]
+	pub fn properties(&self, id: InstanceId) -> Option<&Properties> {
+		self.properties.get(&id)
+	}
+}
@@ -0,0 +1,152 @@
+use std::{
+	error, fmt,
+	time::{Duration, Instant},
+};
+
+use crate::{client::ClientId, game::Context, packet::Packet};
+
+/// Identifies a request made with [`Context::request`], unique to the client that made it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, bitcode::Encode, bitcode::Decode)]
+pub struct RequestId(u32);
+
+impl RequestId {
+	#[must_use]
+	pub fn new(id: u32) -> Self {
+		Self(id)
+	}
+}
+
+/// Why a request did not receive a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcError {
+	/// No response was received before the timeout.
+	TimedOut,
+	/// The connection to the server ended before a response was received.
+	Disconnected,
+}
+
+impl fmt::Display for RpcError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::TimedOut => write!(f, "request timed out"),
+			Self::Disconnected => write!(f, "disconnected before a response was received"),
+		}
+	}
+}
+
+impl error::Error for RpcError {}
+
+/// Called with the response to a request, or the reason there was none.
+pub type Callback<M> = Box<dyn FnOnce(&mut Context<M>, Result<M, RpcError>)>;
+
+/// A request waiting for its response.
+pub(crate) struct PendingRequest<M> {
+	to: ClientId,
+	deadline: Instant,
+	callback: Callback<M>,
+}
+
+impl<M> Context<M> {
+	/// Sends a request to a client, calling `callback` with its response once it
+	/// arrives, or with an [`RpcError`] if it does not arrive within `timeout`.
+	///
+	/// The receiver handles the request in [`App::on_request`](crate::App::on_request).
+	/// Clients can only send requests to [`ClientId::SERVER`], and the server can only
+	/// send requests to remote clients.
+	///
+	/// Callbacks are called on the main thread, right before [`App::on_packet`](crate::App::on_packet)
+	/// would be called for the response.
+	///
+	/// # Examples
+	///
+	/// ```rust,ignore
+	/// ctx.request(
+	///     ClientId::SERVER,
+	///     Message::Buy { item },
+	///     Duration::from_secs(5),
+	///     |ctx, response| match response {
+	///         Ok(Message::Bought) => println!("bought {item:?}"),
+	///         Ok(..) => println!("could not buy {item:?}"),
+	///         Err(e) => println!("{e}"),
+	///     },
+	/// );
+	/// ```
+	pub fn request(
+		&mut self,
+		to: ClientId,
+		message: M,
+		timeout: Duration,
+		callback: impl FnOnce(&mut Context<M>, Result<M, RpcError>) + 'static,
+	) -> RequestId {
+		let id = RequestId::new(self.next_request_id);
+
+		self.next_request_id = self.next_request_id.wrapping_add(1);
+		self.requests.insert(
+			id,
+			PendingRequest {
+				to,
+				deadline: Instant::now() + timeout,
+				callback: Box::new(callback),
+			},
+		);
+
+		let _ = self.packet_tx.send(Packet::Request { to, id, message });
+
+		id
+	}
+
+	/// Responds to a request received in [`App::on_request`](crate::App::on_request),
+	/// for requests that cannot be answered right away.
+	pub fn respond(&self, to: ClientId, id: RequestId, message: M) {
+		let _ = self.packet_tx.send(Packet::Response { to, id, message });
+	}
+
+	/// Calls the callback of a request with its result, if it is still pending.
+	pub(crate) fn resolve_request(&mut self, id: RequestId, result: Result<M, RpcError>) {
+		if let Some(request) = self.requests.remove(&id) {
+			(request.callback)(self, result);
+		}
+	}
+
+	/// Calls the callback of a request with the response from `from`, ignoring
+	/// responses from anyone other than the client the request was sent to.
+	pub(crate) fn receive_response(&mut self, from: ClientId, id: RequestId, message: M) {
+		if self
+			.requests
+			.get(&id)
+			.is_some_and(|request| request.to == from)
+		{
+			self.resolve_request(id, Ok(message));
+		}
+	}
+
+	/// Fails the requests that have not received a response before their deadline.
+	pub(crate) fn expire_requests(&mut self) {
+		let now = Instant::now();
+		let expired = self
+			.requests
+			.iter()
+			.filter(|(_, request)| request.deadline <= now)
+			.map(|(id, _)| *id)
+			.collect::<Vec<_>>();
+
+		for id in expired {
+			self.resolve_request(id, Err(RpcError::TimedOut));
+		}
+	}
+
+	/// Fails the pending requests sent to a client that has disconnected, or every
+	/// pending request if `client_id` is `None`.
+	pub(crate) fn fail_requests(&mut self, client_id: Option<ClientId>) {
+		let pending = self
+			.requests
+			.iter()
+			.filter(|(_, request)| client_id.is_none_or(|id| request.to == id))
+			.map(|(id, _)| *id)
+			.collect::<Vec<_>>();
+
+		for id in pending {
+			self.resolve_request(id, Err(RpcError::Disconnected));
+		}
+	}
+}
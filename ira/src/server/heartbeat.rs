@@ -0,0 +1,169 @@
+use std::{
+	collections::BTreeMap,
+	sync::{
+		atomic::{AtomicBool, AtomicI64, Ordering},
+		Mutex,
+	},
+	time::{Duration, Instant},
+};
+
+use crate::client::ClientId;
+
+/// Latency information about a connection, measured with heartbeats.
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkStats {
+	/// The smoothed round-trip time of the connection.
+	pub rtt: Duration,
+	/// The average variation of the round-trip time.
+	pub jitter: Duration,
+	/// When a packet was last received from the connection.
+	pub last_received: Instant,
+}
+
+/// The state of the heartbeats sent to a single connection.
+pub(crate) struct Heartbeat {
+	next_sequence: u32,
+	/// The sequence number and send time of the last ping.
+	ping: Option<(u32, Instant)>,
+	last_ping: Instant,
+	last_received: Instant,
+	rtt: Option<Duration>,
+	jitter: Duration,
+}
+
+impl Heartbeat {
+	pub(crate) fn new() -> Self {
+		let now = Instant::now();
+
+		Self {
+			next_sequence: 0,
+			ping: None,
+			last_ping: now,
+			last_received: now,
+			rtt: None,
+			jitter: Duration::ZERO,
+		}
+	}
+
+	/// Records that a packet has been received from the connection.
+	pub(crate) fn received(&mut self) {
+		self.last_received = Instant::now();
+	}
+
+	/// Returns `true` if nothing has been received within `timeout`.
+	pub(crate) fn timed_out(&self, timeout: Duration) -> bool {
+		self.last_received.elapsed() > timeout
+	}
+
+	/// Returns the sequence number of a new ping if one is due.
+	pub(crate) fn ping(&mut self, interval: Duration) -> Option<u32> {
+		if self.last_ping.elapsed() < interval {
+			return None;
+		}
+
+		let sequence = self.next_sequence;
+
+		self.next_sequence = self.next_sequence.wrapping_add(1);
+		self.last_ping = Instant::now();
+		self.ping = Some((sequence, self.last_ping));
+
+		Some(sequence)
+	}
+
+	/// Updates the round-trip time and jitter from the reply to a ping.
+	pub(crate) fn pong(&mut self, sequence: u32) {
+		let Some((expected, sent)) = self.ping else {
+			return;
+		};
+
+		if expected != sequence {
+			return;
+		}
+
+		let sample = sent.elapsed();
+
+		self.ping = None;
+
+		// smoothed as in RFC 6298 and RFC 3550
+		match self.rtt {
+			None => {
+				self.rtt = Some(sample);
+				self.jitter = sample / 2;
+			}
+			Some(rtt) => {
+				self.jitter = (self.jitter * 15 + rtt.abs_diff(sample)) / 16;
+				self.rtt = Some((rtt * 7 + sample) / 8);
+			}
+		}
+	}
+
+	/// Returns the round-trip time, if it has been measured yet.
+	#[cfg_attr(feature = "server", allow(dead_code))]
+	pub(crate) fn rtt(&self) -> Option<Duration> {
+		self.rtt
+	}
+
+	pub(crate) fn stats(&self) -> Option<NetworkStats> {
+		Some(NetworkStats {
+			rtt: self.rtt?,
+			jitter: self.jitter,
+			last_received: self.last_received,
+		})
+	}
+}
+
+/// A clock synchronized with the server's.
+pub(crate) struct Clock {
+	start: Instant,
+	/// The difference between the server's clock and this one, in microseconds.
+	offset: AtomicI64,
+	#[cfg_attr(feature = "server", allow(dead_code))]
+	synced: AtomicBool,
+}
+
+impl Clock {
+	/// Returns the current time on the server's clock.
+	pub(crate) fn now(&self) -> Duration {
+		let local = self.start.elapsed().as_micros() as i64;
+		let server = local + self.offset.load(Ordering::Relaxed);
+
+		Duration::from_micros(server.max(0) as u64)
+	}
+
+	/// Adjusts the clock with a timestamp from the server, which took `latency` to arrive.
+	#[cfg_attr(feature = "server", allow(dead_code))]
+	pub(crate) fn sync(&self, server_time: Duration, latency: Duration) {
+		let local = self.start.elapsed().as_micros() as i64;
+		let sample = (server_time + latency).as_micros() as i64 - local;
+
+		let offset = if self.synced.swap(true, Ordering::Relaxed) {
+			let offset = self.offset.load(Ordering::Relaxed);
+
+			offset + (sample - offset) / 8
+		} else {
+			sample
+		};
+
+		self.offset.store(offset, Ordering::Relaxed);
+	}
+}
+
+impl Default for Clock {
+	fn default() -> Self {
+		Self {
+			start: Instant::now(),
+			offset: AtomicI64::new(0),
+			synced: false.into(),
+		}
+	}
+}
+
+/// Network state shared between the server thread and the [`Context`](crate::Context).
+#[derive(Default)]
+pub struct Shared {
+	pub(crate) stats: Mutex<BTreeMap<ClientId, NetworkStats>>,
+	/// The number of packets rejected by [`App::validate_packet`](crate::App::validate_packet)
+	/// for each client.
+	pub(crate) rejected: Mutex<BTreeMap<ClientId, u32>>,
+	pub(crate) clock: Clock,
+}
@@ -0,0 +1,137 @@
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	time::{Duration, Instant},
+};
+
+use glam::Vec3;
+
+use crate::{client::ClientId, packet::Packet, server::InstanceId};
+
+/// How often the relevant instances of each client are recomputed, unless an
+/// instance has been created in the meantime.
+const UPDATE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Decides which instances are replicated to each client, provided by
+/// [`NetworkConfig::interest`](crate::server::NetworkConfig::interest).
+///
+/// Instances are relevant to a client based on their distance to its player instance.
+/// Player instances, and instances owned by the client, are always relevant. Clients
+/// are sent a [`Packet::CreateInstance`] when an instance becomes relevant, and a
+/// [`Packet::DeleteInstance`] when it stops being relevant, and nothing in between.
+///
+/// # Examples
+///
+/// ```rust
+/// use ira::{glam::Vec3, server::interest::Interest};
+///
+/// let grid = Interest::Grid { cell_size: 16.0, range: 1 };
+///
+/// assert!(grid.contains(Vec3::ZERO, Vec3::new(31.0, 0.0, -1.0)));
+/// assert!(!grid.contains(Vec3::ZERO, Vec3::new(32.0, 0.0, 0.0)));
+/// assert!(Interest::Radius(10.0).contains(Vec3::ZERO, Vec3::splat(5.0)));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Interest {
+	/// Every instance is replicated to every client.
+	All,
+	/// Instances within this distance of the player are relevant.
+	Radius(f32),
+	/// The world is divided into cubic cells of `cell_size`, and instances are relevant
+	/// if their cell is within `range` cells of the player's cell on every axis.
+	Grid { cell_size: f32, range: u32 },
+	/// Relevance is decided by [`App::is_relevant`](crate::App::is_relevant).
+	Custom,
+}
+
+impl Interest {
+	/// Returns `true` if an instance at `position` is relevant to a player at `player`.
+	///
+	/// This is always `true` for [`Interest::Custom`], which is decided by the [`App`](crate::App).
+	#[must_use]
+	pub fn contains(&self, player: Vec3, position: Vec3) -> bool {
+		match *self {
+			Self::All | Self::Custom => true,
+			Self::Radius(radius) => player.distance_squared(position) <= radius * radius,
+			Self::Grid { cell_size, range } => {
+				let cell = |p: Vec3| (p / cell_size).floor().as_ivec3();
+				let distance = (cell(position) - cell(player)).abs().max_element();
+
+				distance.unsigned_abs() <= range
+			}
+		}
+	}
+}
+
+/// The instances each client has been sent, when interest management is enabled.
+pub(crate) struct Relevance {
+	known: BTreeMap<ClientId, BTreeSet<InstanceId>>,
+	/// Whether an instance has been created since the last update.
+	dirty: bool,
+	last_update: Instant,
+}
+
+impl Default for Relevance {
+	fn default() -> Self {
+		Self {
+			known: BTreeMap::new(),
+			dirty: false,
+			last_update: Instant::now(),
+		}
+	}
+}
+
+impl Relevance {
+	/// Returns `true` if the relevant instances should be recomputed.
+	pub(crate) fn is_due(&self) -> bool {
+		self.dirty || self.last_update.elapsed() >= UPDATE_INTERVAL
+	}
+
+	/// Recomputes the relevant instances on the next update.
+	pub(crate) fn mark_dirty(&mut self) {
+		self.dirty = true;
+	}
+
+	pub(crate) fn mark_updated(&mut self) {
+		self.dirty = false;
+		self.last_update = Instant::now();
+	}
+
+	/// Returns the instances a client has been sent.
+	pub(crate) fn known(&self, client_id: ClientId) -> Option<&BTreeSet<InstanceId>> {
+		self.known.get(&client_id)
+	}
+
+	/// Replaces the instances a client has been sent.
+	pub(crate) fn set_known(&mut self, client_id: ClientId, known: BTreeSet<InstanceId>) {
+		self.known.insert(client_id, known);
+	}
+
+	pub(crate) fn remove_client(&mut self, client_id: ClientId) {
+		self.known.remove(&client_id);
+	}
+
+	/// Returns `true` if a client should receive a packet about a replicated instance,
+	/// which is only the case once it has been told about the instance. Packets about
+	/// anything else are always sent.
+	pub(crate) fn allows<M>(
+		&self,
+		client_id: ClientId,
+		packet: &Packet<M>,
+		is_replicated: impl Fn(InstanceId) -> bool,
+	) -> bool {
+		let (Packet::CreateInstance { id, .. }
+		| Packet::DeleteInstance { id }
+		| Packet::UpdateInstance { id, .. }
+		| Packet::SetOwner { id, .. }
+		| Packet::SetProperty { id, .. }) = packet
+		else {
+			return true;
+		};
+
+		!is_replicated(*id)
+			|| self
+				.known
+				.get(&client_id)
+				.is_some_and(|known| known.contains(id))
+	}
+}
@@ -1,120 +1,383 @@
-use std::{fmt, io, sync::atomic::Ordering};
+use std::{
+	collections::{BTreeMap, BTreeSet},
+	fmt, io, mem,
+	sync::{atomic::Ordering, mpsc},
+	time::Instant,
+};
 
 use tracing::{debug, error, info, warn};
 
 use crate::{
 	client::{Client, ClientId},
-	packet::{self, CreateInstance, Packet, TrustedPacket},
+	handshake::{Handshake, HandshakeResponse, RejectReason},
+	packet::{
+		self, CreateBody, CreateCollider, CreateInstance, CreateJoint, Packet, Recipients,
+		TrustedPacket, UpdateBody, UpdateInstance,
+	},
+	snapshot::{SentSnapshots, Transform},
+	transport::Delivery,
 	App,
 };
 
-use super::{InstanceId, Server};
+use super::{
+	heartbeat::Heartbeat,
+	interest::Interest,
+	room::{RoomId, RoomState},
+	DisconnectReason, Error, InstanceId, JointId, Server,
+};
 
 impl<M> Server<M>
 where
 	M: bitcode::DecodeOwned + bitcode::Encode,
 {
 	/// Spawns a new thread to listen for incoming connections.
-	pub(crate) fn run_listener<A: App<M>>(&self) {
-		let listener = A::listen();
-		let addr = listener.local_addr().unwrap();
+	pub(crate) fn run_listener<A: App<M>>(&self) -> Result<(), Error> {
+		let mut listener = A::listen()?;
 
 		let client_tx = self.client_tx.clone();
 		let mut next_client_id = ClientId::SERVER.next();
 
-		info!(addr = %addr, "listening for incoming connections");
+		info!(addr = ?listener.local_addr(), "listening for incoming connections");
 
 		std::thread::spawn(move || loop {
 			// try to get another connecting client
-			let Ok((stream, _)) = listener.accept() else {
-				continue;
+			let transport = match listener.accept() {
+				Ok(transport) => transport,
+				// the connection was lost before it could be accepted, so wait for the next one
+				Err(e)
+					if matches!(
+						e.kind(),
+						io::ErrorKind::WouldBlock
+							| io::ErrorKind::Interrupted
+							| io::ErrorKind::ConnectionAborted
+					) =>
+				{
+					continue;
+				}
+				Err(e) => {
+					error!(error = ?e, "failed to accept connection, no longer listening");
+					break;
+				}
 			};
 
-			stream.set_nonblocking(true).unwrap();
-			client_tx.send(Client::new(stream, next_client_id)).unwrap();
+			// the server has stopped, so stop accepting connections
+			if client_tx
+				.send(Client::new(transport, next_client_id))
+				.is_err()
+			{
+				break;
+			}
+
 			next_client_id = next_client_id.next();
 		});
+
+		Ok(())
 	}
 
 	fn broadcast(&mut self, packet: &TrustedPacket<M>)
 	where
 		M: fmt::Debug,
 	{
-		debug!(?packet, "sending packet to clients");
+		self.send_to(packet, &Recipients::All);
+	}
+
+	/// Sends a packet to the remote clients among `recipients`. Clients that cannot be
+	/// written to are dropped at the end of the tick.
+	fn send_to(&mut self, packet: &TrustedPacket<M>, recipients: &Recipients)
+	where
+		M: fmt::Debug,
+	{
+		debug!(?packet, ?recipients, "sending packet to clients");
+
+		let room;
+		let recipients = match recipients {
+			Recipients::Room(id) => {
+				room = Recipients::Clients(self.rooms.members(*id).collect());
+				&room
+			}
+			recipients => recipients,
+		};
 
-		packet.write_iter(self.clients.values_mut()).unwrap();
+		let data = bitcode::encode(packet);
+		let delivery = packet.inner.delivery();
+
+		for (client_id, client) in &mut self.clients {
+			if !recipients.contains(*client_id) || !self.rooms.allows(*client_id, packet) {
+				continue;
+			}
+
+			// instances are sent to clients once they become relevant
+			if self.network.interest != Interest::All
+				&& !self.relevance.allows(*client_id, &packet.inner, |id| {
+					self.instances.contains_key(&id)
+				}) {
+				continue;
+			}
+
+			if let Err(e) = client.send(&data, delivery) {
+				self.dropped
+					.push((*client_id, DisconnectReason::Error(e.to_string())));
+			}
+		}
 	}
 
 	fn next_instance_id(&mut self) -> InstanceId {
 		InstanceId::new(self.next_instance_id.fetch_add(1, Ordering::SeqCst))
 	}
 
-	fn process_new_connections(&mut self)
+	/// Completes the handshake of pending connections, accepting or rejecting them.
+	/// Connections that do not send a handshake within the idle timeout are closed.
+	fn process_new_connections<A: App<M>>(&mut self) -> Result<(), Error>
 	where
 		M: fmt::Debug,
 	{
-		while let Ok(mut client) = self.client_rx.try_recv() {
-			let instance_id = self.next_instance_id();
+		for mut client in self.client_rx.try_iter() {
+			client.limit(&self.network);
+			self.pending.push((Instant::now(), client));
+		}
 
-			info!(client_id = ?client.id(), "client connected");
+		for (connected, mut client) in mem::take(&mut self.pending) {
+			let handshake = match client.next_packet() {
+				Ok(data) => bitcode::decode::<Handshake>(&data),
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+					if connected.elapsed() >= self.network.idle_timeout {
+						info!(client_id = ?client.id(), "connection timed out during handshake");
+					} else {
+						self.pending.push((connected, client));
+					}
 
-			Packet::<M>::Connected { instance_id }
-				.into_trusted(client.id())
-				.write(&mut client)
-				.unwrap();
+					continue;
+				}
+				Err(e) => {
+					info!(client_id = ?client.id(), error = ?e, "connection closed during handshake");
+					continue;
+				}
+			};
 
-			// send packets to clients to announce new client
-			let create = Packet::<M>::CreateClient { instance_id }.into_trusted(client.id());
+			let result = handshake
+				.map_err(|_| RejectReason::Malformed)
+				.and_then(|handshake| {
+					self.handshake.validate(&handshake)?;
 
-			self.broadcast(&create);
-			self.packet_tx.send(create).unwrap();
+					A::authenticate(client.id(), &handshake.credentials)
+						.map_err(RejectReason::Unauthorized)
+				});
 
-			// lock for as little time as possible, so just collect immediately
-			let packets = self
-				.instances
-				.iter()
-				.map(|(id, options)| Packet::<M>::CreateInstance {
+			let (response, identity) = match result {
+				Ok(identity) => (HandshakeResponse::Accepted, identity),
+				Err(reason) => {
+					warn!(client_id = ?client.id(), %reason, "rejected connection");
+
+					(HandshakeResponse::Rejected(reason), None)
+				}
+			};
+
+			let accepted = response == HandshakeResponse::Accepted;
+
+			if client
+				.send(&bitcode::encode(&response), Delivery::Reliable)
+				.is_ok() && accepted
+			{
+				self.accept_client(client, identity)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	/// Gives a client its player instance and sends it the state of the world.
+	///
+	/// A client that cannot be written to is dropped before anyone is told about it.
+	fn accept_client(&mut self, mut client: Client, identity: Option<String>) -> Result<(), Error>
+	where
+		M: fmt::Debug,
+	{
+		let instance_id = self.next_instance_id();
+
+		info!(client_id = ?client.id(), "client connected");
+
+		let connected = Packet::<M>::Connected { instance_id }.into_trusted(client.id());
+
+		if let Err(e) = connected.write(&mut client) {
+			info!(client_id = ?client.id(), error = %e, "client disconnected while connecting");
+
+			return Ok(());
+		}
+
+		info!(client_id = ?client.id(), ?identity, "client authenticated");
+
+		// send packets to clients to announce new client, keeping the identity local
+		let create = Packet::<M>::CreateClient {
+			instance_id,
+			identity: None,
+		}
+		.into_trusted(client.id());
+
+		self.broadcast(&create);
+		self.packet_tx.send(
+			Packet::CreateClient {
+				instance_id,
+				identity,
+			}
+			.into_trusted(client.id()),
+		)?;
+
+		// with interest management, instances are sent once they become relevant
+		let replicated = if self.network.interest == Interest::All {
+			&self.instances
+		} else {
+			self.relevance.mark_dirty();
+
+			&BTreeMap::new()
+		};
+
+		// clients start in the default room, and only see what is in it
+		let room = RoomId::DEFAULT;
+		let in_room = |id: &InstanceId| self.rooms.instance(*id).is_none_or(|r| r == room);
+
+		// lock for as little time as possible, so just collect immediately
+		// instances are sent on behalf of their owner, so the client knows who owns them
+		let packets = replicated
+			.iter()
+			.filter(|(id, _)| in_room(id))
+			.map(|(id, options)| {
+				let owner = self.owners.get(id).copied().unwrap_or(ClientId::SERVER);
+
+				Packet::<M>::CreateInstance {
 					id: *id,
 					options: options.clone(),
-				})
-				.collect::<Vec<_>>();
+				}
+				.into_trusted(owner)
+			})
+			.chain(
+				self.joints
+					.iter()
+					.filter(|(_, options)| in_room(&options.instance_a))
+					.map(|(id, options)| Packet::<M>::CreateJoint {
+						id: *id,
+						options: *options,
+					})
+					.chain(
+						self.gravity
+							.map(|gravity| Packet::<M>::SetGravity { gravity }),
+					)
+					.chain(std::iter::once(Packet::<M>::Room {
+						room,
+						state: self.rooms.state(room),
+					}))
+					.map(|packet| packet.into_trusted(ClientId::SERVER)),
+			)
+			.collect::<Vec<_>>();
 
-			for packet in packets {
-				let packet = packet.into_trusted(ClientId::SERVER);
+		self.owners.insert(instance_id, client.id());
+		self.players.insert(client.id(), instance_id);
+		self.rooms.set_client(client.id(), room);
+		self.rooms.set_instance(instance_id, room);
+		self.heartbeats.insert(client.id(), Heartbeat::new());
+		self.snapshots.insert(client.id(), SentSnapshots::default());
 
-				packet.write(&mut client).unwrap();
+		for packet in packets {
+			if let Err(e) = packet.write(&mut client) {
+				self.dropped
+					.push((client.id(), DisconnectReason::Error(e.to_string())));
+				break;
 			}
-
-			self.owners.insert(instance_id, client.id());
-			self.clients.insert(client.id(), client);
 		}
+
+		self.clients.insert(client.id(), client);
+
+		Ok(())
 	}
 
-	fn process_local_packets(&mut self, client_id: ClientId)
+	fn process_local_packets(&mut self, client_id: ClientId) -> Result<(), Error>
 	where
 		M: fmt::Debug,
 	{
-		while let Ok(packet) = self.packet_rx.try_recv() {
+		loop {
+			let packet = match self.packet_rx.try_recv() {
+				Ok(packet) => packet,
+				Err(mpsc::TryRecvError::Empty) => return Ok(()),
+				Err(mpsc::TryRecvError::Disconnected) => return Err(Error::Closed),
+			};
+
+			let packet = match packet {
+				Packet::Message { to, message } => {
+					self.send_to(&Packet::Custom(message).into_trusted(client_id), &to);
+					continue;
+				}
+				Packet::Request { to, .. }
+				| Packet::Response { to, .. }
+				| Packet::InputAck { to, .. } => {
+					self.send_to(&packet.into_trusted(client_id), &Recipients::Client(to));
+					continue;
+				}
+				packet => packet,
+			};
+
 			match &packet {
 				Packet::CreateInstance { options, id } => {
-					self.instances.insert(*id, options.clone());
-					self.owners.insert(*id, client_id);
+					self.create_instance(client_id, *id, options);
 				}
 				Packet::DeleteInstance { id } => {
-					self.instances.remove(id);
-					self.owners.remove(id);
+					self.remove_instance(*id);
 				}
 				Packet::UpdateInstance { id, delta } => {
-					if let Some(instance) = self.instances.get_mut(id) {
-						instance.apply(delta);
-					};
+					self.update_instance(*id, delta);
+
+					// sent to clients in the next snapshot
+					if delta.is_transform_only() {
+						self.set_transform(*id, delta);
+						continue;
+					}
+				}
+				Packet::CreateJoint { options, id } => {
+					self.joints.insert(*id, *options);
+				}
+				Packet::DeleteJoint { id } => {
+					self.joints.remove(id);
+				}
+				Packet::UpdateJoint { id, data } => {
+					if let Some(joint) = self.joints.get_mut(id) {
+						joint.data = *data;
+					}
+				}
+				Packet::SetGravity { gravity } => {
+					self.gravity = Some(*gravity);
+				}
+				Packet::SetProperty { id, key, value } => {
+					self.set_property(*id, key, value);
+				}
+				Packet::JoinRoom { client_id, room } => {
+					self.join_room(*client_id, *room);
+					continue;
+				}
+				Packet::SetOwner { id, owner } => {
+					self.set_owner(*id, *owner);
+					continue;
 				}
-				Packet::DeleteClient => {
+				Packet::SetRoomState { room, state } => {
+					self.set_room_state(*room, *state);
+					continue;
+				}
+				Packet::DeleteClient { .. } => {
 					self.owners.retain(|_, owner| *owner != client_id);
 				}
-				Packet::Custom(..) | Packet::CreateClient { .. } => {}
-				Packet::Connected { .. } => {
-					warn!("received Connected packet from client");
+				Packet::Custom(..)
+				| Packet::Message { .. }
+				| Packet::Request { .. }
+				| Packet::Response { .. }
+				| Packet::InputAck { .. }
+				| Packet::CreateClient { .. } => {}
+				Packet::Connected { .. }
+				| Packet::Input { .. }
+				| Packet::RequestOwnership { .. }
+				| Packet::Spawned { .. }
+				| Packet::Room { .. }
+				| Packet::Snapshot { .. }
+				| Packet::AckSnapshot { .. }
+				| Packet::Ping { .. }
+				| Packet::Pong { .. } => {
+					warn!(?packet, "received unexpected packet from local client");
 					continue;
 				}
 			}
@@ -123,99 +386,899 @@ where
 		}
 	}
 
-	/// Processes local packets and returns the (disconnected clients, (packet owner, packets to send to clients)).
-	fn process_remote_packets(&mut self) -> (Vec<ClientId>, Vec<(ClientId, TrustedPacket<M>)>)
+	/// Checks the parts of a packet received from a client that would panic when applied.
+	fn check_packet(&self, packet: &Packet<M>) -> Result<(), String> {
+		let (model_id, collider) = match packet {
+			Packet::CreateInstance { options, .. } => {
+				(Some(options.model_id), options.collider.as_ref())
+			}
+			Packet::UpdateInstance { delta, .. } => (delta.model_id, delta.collider.as_ref()),
+			_ => return Ok(()),
+		};
+
+		if let Some(model_id) = model_id.filter(|&id| id as usize >= self.models) {
+			return Err(format!("model {model_id} does not exist"));
+		}
+
+		collider.map_or(Ok(()), CreateCollider::validate)
+	}
+
+	/// Checks that a packet received from a client can be applied without panicking,
+	/// then passes it to [`App::validate_packet`], returning it (possibly modified) if
+	/// it was accepted. Clients with too many rejected packets are dropped at the end
+	/// of the tick.
+	fn validate_packet<A: App<M>>(
+		&mut self,
+		client_id: ClientId,
+		mut packet: Packet<M>,
+	) -> Option<Packet<M>>
+	where
+		M: fmt::Debug,
+	{
+		let result = self
+			.check_packet(&packet)
+			.and_then(|()| A::validate_packet(client_id, &mut packet, &self.instances));
+
+		let Err(reason) = result else {
+			return Some(packet);
+		};
+
+		warn!(?client_id, ?packet, %reason, "rejected packet from client");
+
+		// the client has already spawned the instance, so it is told to remove it
+		if let Packet::CreateInstance { id, .. } = packet {
+			let rejected = Packet::Spawned {
+				local_id: id,
+				id: None,
+			}
+			.into_trusted(client_id);
+
+			self.send_to(&rejected, &Recipients::Client(client_id));
+		}
+
+		let rejected = self.rejected.entry(client_id).or_default();
+
+		*rejected += 1;
+
+		self.shared
+			.rejected
+			.lock()
+			.unwrap()
+			.insert(client_id, *rejected);
+
+		if self.network.max_rejected_packets != 0 && *rejected >= self.network.max_rejected_packets
+		{
+			self.dropped.push((client_id, DisconnectReason::Rejected));
+		}
+
+		None
+	}
+
+	/// Delivers messages, requests, responses, inputs and ownership requests from a client
+	/// to the server, since clients cannot send them to anyone else. Other packets are returned as-is.
+	fn forward_to_server(&mut self, client_id: ClientId, packet: Packet<M>) -> Option<Packet<M>>
+	where
+		M: fmt::Debug,
+	{
+		match packet {
+			Packet::Message {
+				to: Recipients::Server,
+				message,
+			} => {
+				let _ = self
+					.packet_tx
+					.send(Packet::Custom(message).into_trusted(client_id));
+
+				None
+			}
+			Packet::Request {
+				to: ClientId::SERVER,
+				..
+			}
+			| Packet::Response {
+				to: ClientId::SERVER,
+				..
+			}
+			| Packet::Input { .. }
+			| Packet::RequestOwnership { .. } => {
+				let _ = self.packet_tx.send(packet.into_trusted(client_id));
+
+				None
+			}
+			Packet::Message { .. } | Packet::Request { .. } | Packet::Response { .. } => {
+				warn!(
+					?client_id,
+					?packet,
+					"client tried to send a message to other clients"
+				);
+
+				None
+			}
+			packet => Some(packet),
+		}
+	}
+
+	/// Processes a packet received from a client, returning the packet owner and packet
+	/// to send to other clients, or `None` if the packet should be dropped.
+	fn process_remote_packet<A: App<M>>(
+		&mut self,
+		client_id: ClientId,
+		packet: Packet<M>,
+	) -> Option<(ClientId, TrustedPacket<M>)>
+	where
+		M: fmt::Debug,
+	{
+		let packet = self.validate_packet::<A>(client_id, packet)?;
+		let mut packet = self.forward_to_server(client_id, packet)?;
+
+		let id = match &mut packet {
+			Packet::CreateInstance { options, id } => {
+				self.spawn_instance(client_id, id, options);
+
+				ClientId::SERVER
+			}
+			Packet::DeleteInstance { id } => {
+				if self.owners.get(id) != Some(&client_id) {
+					warn!(instance_id = ?id, ?client_id, "client tried to delete instance they don't own");
+
+					return None;
+				}
+
+				self.remove_instance(*id);
+
+				ClientId::SERVER
+			}
+			Packet::UpdateInstance { id, delta } => {
+				if self.owners.get(id) != Some(&client_id) {
+					warn!(instance_id = ?id, ?client_id, "client tried to update instance they don't own");
+
+					return None;
+				}
+
+				// the server moves player instances from their inputs instead
+				if self.network.server_authoritative && self.players.get(&client_id) == Some(id) {
+					debug!(instance_id = ?id, ?client_id, "dropping update to player instance");
+
+					return None;
+				}
+
+				self.update_instance(*id, delta);
+
+				client_id
+			}
+			Packet::CreateJoint { options, id } => {
+				if !self.owns_joint(client_id, options) {
+					warn!(
+						?client_id,
+						"client tried to create joint between instances they don't own"
+					);
+
+					return None;
+				}
+
+				*id = JointId::new(self.next_joint_id.fetch_add(1, Ordering::SeqCst));
+				self.joints.insert(*id, *options);
+
+				ClientId::SERVER
+			}
+			Packet::DeleteJoint { id } => {
+				if !self.owns_joint_id(client_id, *id) {
+					warn!(joint_id = ?id, ?client_id, "client tried to delete joint they don't own");
+
+					return None;
+				}
+
+				self.joints.remove(id);
+
+				ClientId::SERVER
+			}
+			Packet::UpdateJoint { id, data } => {
+				if !self.owns_joint_id(client_id, *id) {
+					warn!(joint_id = ?id, ?client_id, "client tried to update joint they don't own");
+
+					return None;
+				}
+
+				if let Some(joint) = self.joints.get_mut(id) {
+					joint.data = *data;
+				}
+
+				client_id
+			}
+			Packet::SetProperty { id, key, value } => {
+				if self.owners.get(id) != Some(&client_id) {
+					warn!(instance_id = ?id, ?client_id, "client tried to set property of instance they don't own");
+
+					return None;
+				}
+
+				self.set_property(*id, key, value);
+
+				client_id
+			}
+			Packet::Custom(..) => client_id,
+
+			Packet::SetGravity { .. } => {
+				warn!(?client_id, "client tried to change gravity");
+
+				return None;
+			}
+			Packet::Connected { .. }
+			| Packet::CreateClient { .. }
+			| Packet::Snapshot { .. }
+			| Packet::InputAck { .. }
+			| Packet::JoinRoom { .. }
+			| Packet::SetRoomState { .. }
+			| Packet::Room { .. }
+			| Packet::SetOwner { .. }
+			| Packet::Spawned { .. } => {
+				warn!(?client_id, "received server-only packet from client");
+
+				return None;
+			}
+			// heartbeats and clients leaving are handled while reading, and messages above
+			Packet::Ping { .. }
+			| Packet::Pong { .. }
+			| Packet::DeleteClient { .. }
+			| Packet::Message { .. }
+			| Packet::Request { .. }
+			| Packet::Response { .. }
+			| Packet::Input { .. }
+			| Packet::RequestOwnership { .. }
+			| Packet::AckSnapshot { .. } => return None,
+		};
+
+		Some((id, packet.into_trusted(client_id)))
+	}
+
+	/// Sends heartbeats to clients and publishes their latency, returning the clients
+	/// that have timed out.
+	fn process_heartbeats(&mut self) -> Vec<(ClientId, DisconnectReason)> {
+		let mut timed_out = Vec::new();
+		let mut stats = self.shared.stats.lock().unwrap();
+
+		for (client_id, heartbeat) in &mut self.heartbeats {
+			if heartbeat.timed_out(self.network.idle_timeout) {
+				info!(?client_id, "client timed out");
+				timed_out.push((*client_id, DisconnectReason::TimedOut));
+				continue;
+			}
+
+			if let Some(sequence) = heartbeat.ping(self.network.heartbeat_interval) {
+				let time = self.shared.clock.now().as_micros() as u64;
+
+				if let Some(client) = self.clients.get_mut(client_id) {
+					let _ = Packet::<M>::Ping { sequence, time }
+						.into_trusted(ClientId::SERVER)
+						.write(client);
+				}
+			}
+
+			if let Some(heartbeat) = heartbeat.stats() {
+				stats.insert(*client_id, heartbeat);
+			}
+		}
+
+		timed_out
+	}
+
+	/// Processes remote packets and returns the (disconnected clients, (packet owner, packets to send to clients)).
+	#[allow(clippy::type_complexity)]
+	fn process_remote_packets<A: App<M>>(
+		&mut self,
+	) -> (
+		Vec<(ClientId, DisconnectReason)>,
+		Vec<(ClientId, TrustedPacket<M>)>,
+	)
 	where
 		M: fmt::Debug,
 	{
 		let mut disconnected = Vec::new();
-		let mut packets = Vec::new();
+		let mut received = Vec::new();
 
 		// then, get any pending packets from clients
 		for client in &mut self.clients.values_mut() {
 			loop {
-				let packet = client.try_read_packet(&self.next_instance_id, &mut self.owners);
+				let packet = client.try_read_packet(&self.owners);
+
+				if let Ok(Some(..)) = packet {
+					if let Some(heartbeat) = self.heartbeats.get_mut(&client.id()) {
+						heartbeat.received();
+					}
+				}
 
 				match packet {
-					Ok(Some(mut packet)) => {
-						let id = match &mut packet {
-							Packet::CreateInstance { options, id } => {
-								*id = InstanceId::new(
-									self.next_instance_id.fetch_add(1, Ordering::SeqCst),
-								);
-
-								self.instances.insert(*id, options.clone());
-								self.owners.insert(*id, client.id());
-
-								ClientId::SERVER
-							}
-							Packet::DeleteInstance { id } => {
-								if self.owners.get(id) != Some(&client.id()) {
-									warn!(instance_id = ?id, client_id = ?client.id(), "client tried to delete instance they don't own");
-
-									continue;
-								}
-
-								self.instances.remove(id);
-								self.owners.remove(id);
-
-								ClientId::SERVER
-							}
-							Packet::UpdateInstance { id, delta } => {
-								if self.owners.get(id) != Some(&client.id()) {
-									warn!(instance_id = ?id, client_id = ?client.id(), "client tried to update instance they don't own");
-
-									continue;
-								}
-
-								if let Some(instance) = self.instances.get_mut(id) {
-									instance.apply(delta);
-								};
-
-								client.id()
-							}
-							Packet::DeleteClient => {
-								self.owners.retain(|_, owner| *owner != client.id());
-
-								client.id()
-							}
-							Packet::Custom(..) | Packet::CreateClient { .. } => client.id(),
-							Packet::Connected { .. } => {
-								warn!("received Connected packet from client");
-
-								continue;
-							}
-						};
-
-						packets.push((id, packet.into_trusted(client.id())));
+					Ok(Some(Packet::Ping { sequence, .. })) => {
+						let _ = Packet::<M>::Pong { sequence }
+							.into_trusted(ClientId::SERVER)
+							.write(client);
+					}
+					Ok(Some(Packet::Pong { sequence })) => {
+						if let Some(heartbeat) = self.heartbeats.get_mut(&client.id()) {
+							heartbeat.pong(sequence);
+						}
 					}
-					Ok(None) => {
+					Ok(Some(Packet::AckSnapshot { sequence })) => {
+						if let Some(snapshots) = self.snapshots.get_mut(&client.id()) {
+							snapshots.ack(sequence);
+						}
+					}
+					Ok(Some(Packet::DeleteClient { .. })) => {
+						info!(client_id = ?client.id(), "client left");
+						disconnected.push((client.id(), DisconnectReason::Left));
 						break;
 					}
+					Ok(Some(packet)) => {
+						received.push((client.id(), packet));
+					}
+					// only this packet was dropped, so keep reading
+					Ok(None) => {}
 					Err(packet::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
 						break;
 					}
-					Err(packet::Error::Io(..)) => {
-						info!(client_id = ?client.id(), "client disconnected");
-						disconnected.push(client.id());
+					Err(packet::Error::RateLimited) => {
+						debug!(client_id = ?client.id(), "client is rate limited");
+						break;
+					}
+					Err(packet::Error::Io(e)) if e.kind() == io::ErrorKind::InvalidData => {
+						warn!(client_id = ?client.id(), error = %e, "disconnecting client that sent an invalid frame");
+						disconnected.push((client.id(), DisconnectReason::InvalidData));
+						break;
+					}
+					Err(packet::Error::Io(e)) => {
+						info!(client_id = ?client.id(), error = %e, "client disconnected");
+						disconnected.push((client.id(), DisconnectReason::Error(e.to_string())));
 						break;
 					}
-					Err(e) => {
-						error!(error = ?e, "error reading packet");
+					Err(packet::Error::Bitcode(e)) => {
+						warn!(client_id = ?client.id(), error = ?e, "disconnecting client that sent a malformed packet");
+						disconnected.push((client.id(), DisconnectReason::InvalidData));
 						break;
 					}
 				}
 			}
 		}
 
+		let packets = received
+			.into_iter()
+			.filter_map(|(client_id, packet)| self.process_remote_packet::<A>(client_id, packet))
+			.collect();
+
 		(disconnected, packets)
 	}
 
+	/// Records the latest transform of an instance, to be sent in the next snapshot.
+	fn set_transform(&mut self, id: InstanceId, delta: &UpdateInstance) {
+		let transform = Transform::quantize(
+			delta.position,
+			delta.rotation,
+			self.network.position_precision,
+		);
+
+		if self.transforms.insert(id, transform) != Some(transform) {
+			self.transforms_changed = true;
+		}
+	}
+
+	/// Sends a snapshot of the changed transforms to every client, or resends the
+	/// latest one to clients that have not acknowledged it.
+	fn send_snapshots(&mut self)
+	where
+		M: fmt::Debug,
+	{
+		let changed = mem::take(&mut self.transforms_changed);
+
+		for (client_id, client) in &mut self.clients {
+			let Some(snapshots) = self.snapshots.get_mut(client_id) else {
+				continue;
+			};
+
+			if !changed && !snapshots.needs_resend() {
+				continue;
+			}
+
+			let known = self.relevance.known(*client_id);
+			let room = self.rooms.client(*client_id);
+
+			// clients are the authority over their own instances, and only know
+			// about the instances in their room that are relevant to them
+			let (sequence, baseline, entries) = snapshots.encode(
+				self.transforms
+					.iter()
+					.filter(|(id, _)| self.owners.get(id) != Some(client_id))
+					.filter(|(id, _)| self.rooms.instance(**id).is_none_or(|r| r == room))
+					.filter(|(id, _)| {
+						self.network.interest == Interest::All
+							|| !self.instances.contains_key(id)
+							|| known.is_some_and(|known| known.contains(id))
+					}),
+			);
+
+			let packet = Packet::<M>::Snapshot {
+				sequence,
+				baseline,
+				entries,
+			}
+			.into_trusted(ClientId::SERVER);
+
+			if let Err(e) = packet.write(client) {
+				self.dropped
+					.push((*client_id, DisconnectReason::Error(e.to_string())));
+			}
+		}
+	}
+
+	/// Moves a client into a room, replacing the clients and instances of its previous
+	/// room with the ones in the new room. The server itself sees every room, so moving
+	/// it only changes the room of the instances it creates.
+	fn join_room(&mut self, client_id: ClientId, room: RoomId)
+	where
+		M: fmt::Debug,
+	{
+		let previous = self.rooms.client(client_id);
+
+		if client_id == ClientId::SERVER {
+			self.rooms.set_client(client_id, room);
+			return;
+		}
+
+		if previous == room || !self.clients.contains_key(&client_id) {
+			return;
+		}
+
+		info!(?client_id, ?previous, ?room, "client joined room");
+
+		let player = self.players.get(&client_id).copied();
+
+		// ownership is scoped to the room, so the instances stay behind with the server
+		let owned = self
+			.owners
+			.iter()
+			.filter(|(id, owner)| {
+				**owner == client_id
+					&& Some(**id) != player
+					&& self.rooms.instance(**id) == Some(previous)
+			})
+			.map(|(id, _)| *id)
+			.collect::<Vec<_>>();
+
+		for id in owned {
+			self.set_owner(id, ClientId::SERVER);
+		}
+
+		// the client and its previous room stop seeing each other, except for its own
+		// player, which moves with it
+		let left = self.others_in_room(client_id, previous);
+		let hidden = self
+			.instances_in_room(previous)
+			.into_iter()
+			.filter(|id| Some(*id) != player);
+
+		self.send_to(
+			&Packet::<M>::DeleteClient {
+				reason: DisconnectReason::LeftRoom,
+			}
+			.into_trusted(client_id),
+			&Recipients::Clients(left.clone()),
+		);
+
+		for other in left {
+			self.send_to(
+				&Packet::<M>::DeleteClient {
+					reason: DisconnectReason::LeftRoom,
+				}
+				.into_trusted(other),
+				&Recipients::Client(client_id),
+			);
+		}
+
+		for id in hidden {
+			self.send_to(
+				&Packet::<M>::DeleteInstance { id }.into_trusted(ClientId::SERVER),
+				&Recipients::Client(client_id),
+			);
+		}
+
+		self.rooms.set_client(client_id, room);
+		self.relevance
+			.set_known(client_id, player.into_iter().collect());
+		self.relevance.mark_dirty();
+
+		// then the client and its new room see each other
+		let joined = self.others_in_room(client_id, room);
+
+		if let Some(instance_id) = player {
+			self.rooms.set_instance(instance_id, room);
+			self.send_to(
+				&Packet::<M>::CreateClient {
+					instance_id,
+					identity: None,
+				}
+				.into_trusted(client_id),
+				&Recipients::Clients(joined.clone()),
+			);
+		}
+
+		for other in joined {
+			let Some(&instance_id) = self.players.get(&other) else {
+				continue;
+			};
+
+			self.send_to(
+				&Packet::<M>::CreateClient {
+					instance_id,
+					identity: None,
+				}
+				.into_trusted(other),
+				&Recipients::Client(client_id),
+			);
+		}
+
+		// with interest management, instances are sent once they become relevant
+		if self.network.interest == Interest::All {
+			self.show_room(client_id, room);
+		}
+
+		self.send_to(
+			&Packet::<M>::Room {
+				room,
+				state: self.rooms.state(room),
+			}
+			.into_trusted(ClientId::SERVER),
+			&Recipients::Client(client_id),
+		);
+	}
+
+	/// Gives the ownership of an instance to a client, or to the server, telling every
+	/// client that can see the instance and the local context.
+	fn set_owner(&mut self, id: InstanceId, owner: ClientId)
+	where
+		M: fmt::Debug,
+	{
+		if !self.instances.contains_key(&id) {
+			warn!(instance_id = ?id, "tried to change the owner of an unknown instance");
+			return;
+		}
+
+		if owner != ClientId::SERVER && !self.clients.contains_key(&owner) {
+			warn!(instance_id = ?id, ?owner, "tried to give an instance to an unknown client");
+			return;
+		}
+
+		debug!(instance_id = ?id, ?owner, "changing owner");
+
+		self.owners.insert(id, owner);
+
+		let packet = Packet::SetOwner { id, owner }.into_trusted(ClientId::SERVER);
+
+		self.broadcast(&packet);
+
+		let _ = self.packet_tx.send(packet);
+	}
+
+	/// Records an instance created by a client, which is placed in the client's room.
+	fn create_instance(&mut self, client_id: ClientId, id: InstanceId, options: &CreateInstance) {
+		self.instances.insert(id, options.clone());
+		self.owners.insert(id, client_id);
+		self.rooms.set_instance(id, self.rooms.client(client_id));
+		self.relevance.mark_dirty();
+	}
+
+	/// Gives an instance spawned by a remote client a new id and records it. The client
+	/// has already spawned the instance with a local id, and learns its new id here.
+	fn spawn_instance(&mut self, client_id: ClientId, id: &mut InstanceId, options: &CreateInstance)
+	where
+		M: fmt::Debug,
+	{
+		let local_id = mem::replace(id, self.next_instance_id());
+
+		self.create_instance(client_id, *id, options);
+
+		let spawned = Packet::Spawned {
+			local_id,
+			id: Some(*id),
+		}
+		.into_trusted(client_id);
+
+		self.send_to(&spawned, &Recipients::Client(client_id));
+	}
+
+	/// Returns `true` if the client owns every instance connected by the joint that isn't
+	/// static, and at least one of them.
+	fn owns_joint(&self, client_id: ClientId, joint: &CreateJoint) -> bool {
+		let owns = |id| self.owners.get(&id) == Some(&client_id);
+		let is_static = |id| {
+			self.instances
+				.get(&id)
+				.is_some_and(|instance| matches!(instance.body, CreateBody::Static))
+		};
+
+		[joint.instance_a, joint.instance_b]
+			.into_iter()
+			.all(|id| owns(id) || is_static(id))
+			&& (owns(joint.instance_a) || owns(joint.instance_b))
+	}
+
+	/// Returns `true` if the joint exists and the client owns it, as with [`Self::owns_joint`].
+	fn owns_joint_id(&self, client_id: ClientId, id: JointId) -> bool {
+		self.joints
+			.get(&id)
+			.is_some_and(|joint| self.owns_joint(client_id, joint))
+	}
+
+	/// Applies an update to an instance, forgetting its joints if it becomes static since
+	/// they're removed along with its rigidbody.
+	fn update_instance(&mut self, id: InstanceId, delta: &UpdateInstance) {
+		if let Some(instance) = self.instances.get_mut(&id) {
+			instance.apply(delta);
+		}
+
+		if matches!(delta.body, Some(UpdateBody::Static)) {
+			self.joints
+				.retain(|_, joint| joint.instance_a != id && joint.instance_b != id);
+		}
+	}
+
+	/// Forgets an instance that has been deleted, along with its joints.
+	fn remove_instance(&mut self, id: InstanceId) {
+		self.instances.remove(&id);
+		self.transforms.remove(&id);
+		self.owners.remove(&id);
+		self.rooms.remove_instance(id);
+		self.joints
+			.retain(|_, joint| joint.instance_a != id && joint.instance_b != id);
+	}
+
+	/// Keeps the latest value of a property of a replicated instance, for the clients
+	/// it is sent to later.
+	fn set_property(&mut self, id: InstanceId, key: &str, value: &[u8]) {
+		if let Some(instance) = self.instances.get_mut(&id) {
+			instance.properties.set_encoded(key, value.to_vec());
+		}
+	}
+
+	/// Changes the state of a room, telling the clients in it.
+	fn set_room_state(&mut self, room: RoomId, state: RoomState)
+	where
+		M: fmt::Debug,
+	{
+		self.rooms.set_state(room, state);
+		self.send_to(
+			&Packet::Room { room, state }.into_trusted(ClientId::SERVER),
+			&Recipients::Room(room),
+		);
+	}
+
+	/// Returns the remote clients in a room, other than `client_id`.
+	fn others_in_room(&self, client_id: ClientId, room: RoomId) -> Vec<ClientId> {
+		self.rooms
+			.members(room)
+			.filter(|id| *id != client_id)
+			.collect()
+	}
+
+	/// Returns the replicated instances in a room.
+	fn instances_in_room(&self, room: RoomId) -> Vec<InstanceId> {
+		self.instances
+			.keys()
+			.filter(|id| self.rooms.instance(**id) == Some(room))
+			.copied()
+			.collect()
+	}
+
+	/// Sends the instances and joints in a room to a client, except for its own player.
+	fn show_room(&mut self, client_id: ClientId, room: RoomId)
+	where
+		M: fmt::Debug,
+	{
+		let player = self.players.get(&client_id).copied();
+		let packets = self
+			.instances_in_room(room)
+			.into_iter()
+			.filter(|id| Some(*id) != player)
+			.filter_map(|id| {
+				let owner = self.owners.get(&id).copied().unwrap_or(ClientId::SERVER);
+				let options = self.instances.get(&id)?.clone();
+
+				Some(Packet::<M>::CreateInstance { options, id }.into_trusted(owner))
+			})
+			.chain(
+				self.joints
+					.iter()
+					.filter(|(_, joint)| self.rooms.instance(joint.instance_a) == Some(room))
+					.map(|(id, options)| {
+						Packet::<M>::CreateJoint {
+							id: *id,
+							options: *options,
+						}
+						.into_trusted(ClientId::SERVER)
+					}),
+			)
+			.collect::<Vec<_>>();
+
+		for packet in packets {
+			self.send_to(&packet, &Recipients::Client(client_id));
+		}
+	}
+
+	/// Returns the position of a client's player instance, if it is known.
+	fn player_position(&self, client_id: ClientId) -> Option<glam::Vec3> {
+		let id = self.players.get(&client_id)?;
+
+		self.transforms.get(id).map_or_else(
+			|| self.instances.get(id).map(|instance| instance.position),
+			|transform| Some(transform.dequantize(self.network.position_precision).0),
+		)
+	}
+
+	/// Recomputes the instances relevant to each client, sending the instances that
+	/// have become relevant and deleting the ones that no longer are.
+	///
+	/// Clients whose player has not been positioned yet keep their relevant instances.
+	fn update_interest<A: App<M>>(&mut self)
+	where
+		M: fmt::Debug,
+	{
+		if self.network.interest == Interest::All || !self.relevance.is_due() {
+			return;
+		}
+
+		self.relevance.mark_updated();
+
+		let client_ids = self.clients.keys().copied().collect::<Vec<_>>();
+
+		for client_id in client_ids {
+			let Some(player) = self.player_position(client_id) else {
+				continue;
+			};
+
+			let room = self.rooms.client(client_id);
+			let relevant = self
+				.instances
+				.iter()
+				.filter(|(id, _)| self.rooms.instance(**id).is_none_or(|r| r == room))
+				.filter(|(id, instance)| {
+					self.owners.get(id) == Some(&client_id)
+						|| self.players.values().any(|p| p == *id)
+						|| match self.network.interest {
+							Interest::Custom => A::is_relevant(client_id, player, **id, instance),
+							interest => interest.contains(player, instance.position),
+						}
+				})
+				.map(|(id, _)| *id)
+				.collect::<BTreeSet<_>>();
+
+			let known = self.relevance.known(client_id).cloned().unwrap_or_default();
+
+			// deleted instances have already been sent to the client
+			for &id in known.difference(&relevant) {
+				if self.instances.contains_key(&id) {
+					self.send_to(
+						&Packet::<M>::DeleteInstance { id }.into_trusted(ClientId::SERVER),
+						&Recipients::Client(client_id),
+					);
+				}
+			}
+
+			self.relevance.set_known(client_id, relevant.clone());
+
+			for &id in relevant.difference(&known) {
+				let Some(options) = self.instances.get(&id) else {
+					continue;
+				};
+
+				let owner = self.owners.get(&id).copied().unwrap_or(ClientId::SERVER);
+				let packet = Packet::<M>::CreateInstance {
+					options: options.clone(),
+					id,
+				}
+				.into_trusted(owner);
+
+				self.send_to(&packet, &Recipients::Client(client_id));
+			}
+
+			// joints are only created once both of their instances exist
+			let joints = self
+				.joints
+				.iter()
+				.filter(|(_, joint)| {
+					let replicated = |id| self.instances.contains_key(id);
+					let was_visible = |id| known.contains(id) || !replicated(id);
+					let visible = |id| relevant.contains(id) || !replicated(id);
+
+					!(was_visible(&joint.instance_a) && was_visible(&joint.instance_b))
+						&& visible(&joint.instance_a)
+						&& visible(&joint.instance_b)
+				})
+				.map(|(id, options)| Packet::<M>::CreateJoint {
+					id: *id,
+					options: *options,
+				})
+				.collect::<Vec<_>>();
+
+			for packet in joints {
+				self.send_to(
+					&packet.into_trusted(ClientId::SERVER),
+					&Recipients::Client(client_id),
+				);
+			}
+		}
+	}
+
+	/// Drops clients from the server, telling everyone else why they left.
+	///
+	/// Only the first reason is used for a client that is disconnected more than once.
+	fn disconnect_clients(
+		&mut self,
+		mut disconnected: Vec<(ClientId, DisconnectReason)>,
+	) -> Result<(), Error>
+	where
+		M: fmt::Debug,
+	{
+		while !disconnected.is_empty() {
+			for (client_id, reason) in mem::take(&mut disconnected) {
+				let Some(mut client) = self.clients.remove(&client_id) else {
+					continue;
+				};
+
+				info!(?client_id, %reason, "dropping client");
+
+				let packet = Packet::<M>::DeleteClient { reason }.into_trusted(client_id);
+
+				// let the client know why, if it is still listening
+				let _ = packet.write(&mut client);
+
+				self.heartbeats.remove(&client_id);
+				self.snapshots.remove(&client_id);
+				self.rejected.remove(&client_id);
+				self.relevance.remove_client(client_id);
+				self.shared.stats.lock().unwrap().remove(&client_id);
+				self.shared.rejected.lock().unwrap().remove(&client_id);
+
+				// the instances it created outlive it, owned by the server
+				let owned = self
+					.owners
+					.iter()
+					.filter(|(id, owner)| {
+						**owner == client_id && self.players.get(&client_id) != Some(id)
+					})
+					.map(|(id, _)| *id)
+					.collect::<Vec<_>>();
+
+				self.owners.retain(|_, owner| *owner != client_id);
+
+				for id in owned {
+					self.set_owner(id, ClientId::SERVER);
+				}
+
+				// only the client's room is told, so it is forgotten afterwards
+				self.broadcast(&packet);
+				self.packet_tx.send(packet)?;
+
+				if let Some(player) = self.players.remove(&client_id) {
+					self.rooms.remove_instance(player);
+				}
+
+				self.rooms.remove_client(client_id);
+			}
+
+			// broadcasting may have failed for other clients
+			disconnected = mem::take(&mut self.dropped);
+		}
+
+		Ok(())
+	}
+
 	/// Runs a dedicated server. This is used when the "server" feature is enabled.
 	///
 	/// Packets received from `packet_rx` are converted into trusted packets, treating
-	/// the local client as an authority.
-	pub(crate) fn run_server(mut self, local: CreateInstance)
+	/// the local client as an authority. Clients that fail are dropped on their own,
+	/// and the server only stops once the local [`Context`](crate::Context) is dropped.
+	///
+	/// # Errors
+	///
+	/// Returns [`Error::Closed`] once the local context has been dropped.
+	pub(crate) fn run_server<A: App<M>>(mut self, local: CreateInstance) -> Result<(), Error>
 	where
 		M: fmt::Debug,
 	{
@@ -226,43 +1289,410 @@ where
 			let instance_id = self.next_instance_id();
 
 			self.packet_tx
-				.send(Packet::<M>::Connected { instance_id }.into_trusted(client_id))
-				.unwrap();
+				.send(Packet::<M>::Connected { instance_id }.into_trusted(client_id))?;
 
 			self.instances.insert(instance_id, local);
+			self.players.insert(client_id, instance_id);
+			self.rooms.set_instance(instance_id, RoomId::DEFAULT);
 		}
 
 		loop {
 			// try getting new clients
-			self.process_new_connections();
+			self.process_new_connections::<A>()?;
 
 			// first, get a pending packet from the local client
-			self.process_local_packets(client_id);
+			self.process_local_packets(client_id)?;
+
+			let (mut disconnected, packets) = self.process_remote_packets::<A>();
+
+			disconnected.extend(self.process_heartbeats());
 
-			let (disconnected, packets) = self.process_remote_packets();
+			for (client_id, packet) in packets {
+				match &packet.inner {
+					Packet::UpdateInstance { id, delta } if delta.is_transform_only() => {
+						self.set_transform(*id, delta);
+					}
+					_ => self.send_to(&packet, &Recipients::AllExcept(client_id)),
+				}
 
-			if !disconnected.is_empty() {
-				self.owners
-					.retain(|_, owner| disconnected.binary_search(owner).is_ok());
+				self.packet_tx.send(packet)?;
 			}
 
-			for client_id in disconnected {
-				self.clients.remove(&client_id);
+			self.update_interest::<A>();
+			self.send_snapshots();
+
+			disconnected.append(&mut self.dropped);
+
+			self.disconnect_clients(disconnected)?;
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::{atomic::AtomicU32, Arc};
+
+	use ira_drum::Drum;
+	use rapier3d::dynamics::FixedJointBuilder;
+
+	use super::*;
+	use crate::{
+		handshake,
+		packet::{
+			tests::{samples, Rng},
+			JointData,
+		},
+		server::NetworkConfig,
+		transport::channel,
+		Context, InstanceBuilder,
+	};
+
+	struct TestApp;
+
+	impl App<String> for TestApp {
+		fn create_player(_: &mut Context<String>) -> (u32, InstanceBuilder) {
+			(0, InstanceBuilder::default())
+		}
+
+		fn on_init() -> Drum {
+			Drum {
+				textures: Box::default(),
+				materials: Box::default(),
+				meshes: Box::default(),
+				models: Box::default(),
+				lights: Box::default(),
+				brdf_lut: None,
+				irradiance_map: None,
+				prefiltered_map: None,
 			}
+		}
 
-			for (client_id, packet) in packets {
-				debug!(?packet, "sending packet to other clients");
+		fn on_ready(_: &mut Context<String>) -> Self {
+			Self
+		}
+	}
 
-				packet
-					.write_iter(
-						self.clients
-							.iter_mut()
-							.filter_map(|(id, stream)| (client_id != *id).then_some(stream)),
-					)
+	/// Creates a server with a single model and one connected client, returning the
+	/// server and the client's end of the connection.
+	fn server() -> (Server<String>, Client) {
+		let (_, packet_rx) = mpsc::channel();
+		let (packet_tx, _) = mpsc::channel();
+		let (client_tx, client_rx) = mpsc::channel();
+
+		let mut server = Server::new(
+			packet_rx,
+			packet_tx,
+			client_tx,
+			client_rx,
+			Arc::new(AtomicU32::new(1)),
+			Arc::new(AtomicU32::new(0)),
+			Handshake {
+				protocol_version: handshake::PROTOCOL_VERSION,
+				app_id: String::new(),
+				schema_hash: 0,
+				credentials: Vec::new(),
+			},
+			1,
+			NetworkConfig::default(),
+			Arc::default(),
+		);
+
+		let (local, remote) = channel::pair();
+		let client_id = ClientId::SERVER.next();
+
+		server
+			.clients
+			.insert(client_id, Client::new(Box::new(remote), client_id));
+
+		(server, Client::new(Box::new(local), ClientId::SERVER))
+	}
+
+	#[test]
+	fn fuzzed_packets_from_clients_are_safe_to_apply() {
+		let (mut server, mut client) = server();
+		let mut rng = Rng::new();
+
+		for packet in samples() {
+			let valid = bitcode::encode(&packet);
+
+			for _ in 0..500 {
+				client
+					.send(&rng.mutate(&valid), Delivery::Reliable)
 					.unwrap();
 
-				self.packet_tx.send(packet).unwrap();
+				let (_, accepted) = server.process_remote_packets::<TestApp>();
+
+				// the host applies accepted packets to its own world
+				for (_, packet) in accepted {
+					let (model_id, collider) = match packet.inner {
+						Packet::CreateInstance { options, .. } => {
+							(Some(options.model_id), options.collider)
+						}
+						Packet::UpdateInstance { delta, .. } => (delta.model_id, delta.collider),
+						_ => continue,
+					};
+
+					assert!(model_id.is_none_or(|id| id < 1));
+
+					if let Some(collider) = collider {
+						let _ = collider.to_builder();
+					}
+				}
+			}
+		}
+	}
+
+	#[test]
+	fn invalid_instances_from_clients_are_rejected() {
+		let (mut server, mut client) = server();
+		let client_id = ClientId::SERVER.next();
+
+		let mut packets = samples().into_iter().filter_map(|packet| match packet {
+			Packet::CreateInstance { options, id } => Some((options, id)),
+			_ => None,
+		});
+
+		let (mut missing_model, id) = packets.next().unwrap();
+		let (mut invalid_collider, _) = packets.next().unwrap();
+
+		missing_model.model_id = 1;
+		invalid_collider.collider = Some(CreateCollider::TriMesh {
+			vertices: vec![glam::Vec3::ZERO],
+			indices: vec![[0, 1, 2]],
+		});
+
+		for options in [missing_model, invalid_collider] {
+			let packet = Packet::<String>::CreateInstance { options, id };
+
+			client
+				.send(&bitcode::encode(&packet), Delivery::Reliable)
+				.unwrap();
+
+			let (_, accepted) = server.process_remote_packets::<TestApp>();
+
+			assert!(accepted.is_empty());
+		}
+
+		assert_eq!(server.rejected.get(&client_id), Some(&2));
+		assert!(server.instances.is_empty());
+
+		// the client is told to remove the instances it has already spawned
+		for _ in 0..2 {
+			let packet = TrustedPacket::<String>::read(&mut client).unwrap();
+
+			assert!(matches!(
+				packet.inner,
+				Packet::Spawned { local_id, id: None } if local_id == id
+			));
+		}
+	}
+
+	#[test]
+	fn players_move_between_rooms_with_their_client() {
+		let (mut server, mut client) = server();
+		let client_id = ClientId::SERVER.next();
+		let room = RoomId::new(1);
+
+		let Some(Packet::CreateInstance { options, .. }) = samples().into_iter().next() else {
+			unreachable!();
+		};
+
+		let [player, left_behind, joined] = [1, 2, 3].map(InstanceId::new);
+
+		for (id, room) in [
+			(player, RoomId::DEFAULT),
+			(left_behind, RoomId::DEFAULT),
+			(joined, room),
+		] {
+			server.instances.insert(id, options.clone());
+			server.owners.insert(id, ClientId::SERVER);
+			server.rooms.set_instance(id, room);
+		}
+
+		server.players.insert(client_id, player);
+		server.owners.insert(player, client_id);
+		server.join_room(client_id, room);
+
+		let mut deleted = Vec::new();
+		let mut created = Vec::new();
+
+		while let Ok(packet) = TrustedPacket::<String>::read(&mut client) {
+			match packet.inner {
+				Packet::DeleteInstance { id } => deleted.push(id),
+				Packet::CreateInstance { id, .. } => created.push(id),
+				_ => {}
 			}
 		}
+
+		assert_eq!(deleted, [left_behind]);
+		assert_eq!(created, [joined]);
+		assert_eq!(server.rooms.instance(player), Some(room));
+	}
+
+	/// Sends a packet from the client, returning the packets accepted by the server.
+	fn send(
+		server: &mut Server<String>,
+		client: &mut Client,
+		packet: &Packet<String>,
+	) -> Vec<(ClientId, TrustedPacket<String>)> {
+		client
+			.send(&bitcode::encode(packet), Delivery::Reliable)
+			.unwrap();
+
+		server.process_remote_packets::<TestApp>().1
+	}
+
+	/// Inserts rigid instances into the server, owned by the given clients.
+	fn insert_instances<const N: usize>(
+		server: &mut Server<String>,
+		owners: [ClientId; N],
+	) -> [InstanceId; N] {
+		let Some(Packet::CreateInstance { options, .. }) = samples().into_iter().next() else {
+			unreachable!();
+		};
+
+		let mut next = 0;
+
+		owners.map(|owner| {
+			next += 1;
+
+			let id = InstanceId::new(next);
+
+			server.instances.insert(id, options.clone());
+			server.owners.insert(id, owner);
+			server.rooms.set_instance(id, RoomId::DEFAULT);
+
+			id
+		})
+	}
+
+	fn joint(instance_a: InstanceId, instance_b: InstanceId) -> CreateJoint {
+		CreateJoint {
+			instance_a,
+			instance_b,
+			data: JointData::from_joint(&FixedJointBuilder::new().build().into()),
+		}
+	}
+
+	#[test]
+	fn joints_from_clients_are_replicated() {
+		let (mut server, mut client) = server();
+		let [a, b] = insert_instances(&mut server, [ClientId::SERVER.next(); 2]);
+
+		let options = joint(a, b);
+		let create = Packet::CreateJoint {
+			options,
+			id: JointId::new(u32::MAX),
+		};
+
+		assert_eq!(send(&mut server, &mut client, &create).len(), 1);
+
+		let (&id, _) = server.joints.iter().next().unwrap();
+
+		assert_eq!(server.joints.len(), 1);
+		assert_ne!(id, JointId::new(u32::MAX));
+
+		let mut data = options.data;
+		data.contacts_enabled = !data.contacts_enabled;
+
+		let update = Packet::UpdateJoint { id, data };
+
+		assert_eq!(send(&mut server, &mut client, &update).len(), 1);
+		assert_eq!(
+			server.joints[&id].data.contacts_enabled,
+			data.contacts_enabled
+		);
+
+		let delete = Packet::DeleteJoint { id };
+
+		assert_eq!(send(&mut server, &mut client, &delete).len(), 1);
+		assert!(server.joints.is_empty());
+	}
+
+	#[test]
+	fn joints_to_instances_owned_by_others_are_rejected() {
+		let client_id = ClientId::SERVER.next();
+		let (mut server, mut client) = server();
+		let [owned, other] = insert_instances(&mut server, [client_id, ClientId::SERVER]);
+
+		let create = Packet::CreateJoint {
+			options: joint(owned, other),
+			id: JointId::new(0),
+		};
+
+		assert!(send(&mut server, &mut client, &create).is_empty());
+		assert!(server.joints.is_empty());
+
+		// static instances can't be moved by the joint, so anyone can attach to them
+		server.instances.get_mut(&other).unwrap().body = CreateBody::Static;
+
+		assert_eq!(send(&mut server, &mut client, &create).len(), 1);
+		assert_eq!(server.joints.len(), 1);
+	}
+
+	#[test]
+	fn joints_are_forgotten_when_an_instance_becomes_static() {
+		let (mut server, mut client) = server();
+		let [a, b] = insert_instances(&mut server, [ClientId::SERVER.next(); 2]);
+
+		let create = Packet::CreateJoint {
+			options: joint(a, b),
+			id: JointId::new(0),
+		};
+
+		send(&mut server, &mut client, &create);
+
+		let mut delta = UpdateInstance::transform(glam::Vec3::ZERO, glam::Quat::IDENTITY);
+		delta.body = Some(UpdateBody::Static);
+
+		let update = Packet::UpdateInstance { id: a, delta };
+
+		assert_eq!(send(&mut server, &mut client, &update).len(), 1);
+		assert!(server.joints.is_empty());
+	}
+
+	#[test]
+	fn clients_can_delete_instances_they_spawned() {
+		let (mut server, mut client) = server();
+
+		let Some(create) = samples().into_iter().next() else {
+			unreachable!();
+		};
+
+		assert_eq!(send(&mut server, &mut client, &create).len(), 1);
+
+		let Packet::Spawned { id: Some(id), .. } =
+			TrustedPacket::<String>::read(&mut client).unwrap().inner
+		else {
+			panic!("expected the instance to be spawned");
+		};
+
+		let delete = Packet::DeleteInstance { id };
+
+		assert_eq!(send(&mut server, &mut client, &delete).len(), 1);
+		assert!(server.instances.is_empty());
+		assert!(server.owners.is_empty());
+	}
+
+	#[test]
+	fn dropped_packets_do_not_stop_reading() {
+		let (mut server, mut client) = server();
+		let [owned, other] =
+			insert_instances(&mut server, [ClientId::SERVER.next(), ClientId::SERVER]);
+
+		for packet in [
+			Packet::<String>::DeleteInstance { id: other },
+			Packet::DeleteInstance { id: owned },
+		] {
+			client
+				.send(&bitcode::encode(&packet), Delivery::Reliable)
+				.unwrap();
+		}
+
+		let (_, accepted) = server.process_remote_packets::<TestApp>();
+
+		assert_eq!(accepted.len(), 1);
+		assert!(server.instances.contains_key(&other));
+		assert!(!server.instances.contains_key(&owned));
 	}
 }
@@ -1,20 +1,184 @@
+pub mod heartbeat;
+pub mod interest;
 #[cfg(feature = "server")]
 pub mod local;
 #[cfg(all(not(feature = "server"), feature = "client"))]
 pub mod remote;
+pub mod room;
 
 use std::{
 	collections::BTreeMap,
-	fmt,
+	error, fmt, io,
 	sync::{atomic::AtomicU32, mpsc, Arc},
+	time::{Duration, Instant},
 };
 
 use crate::{
 	client::{Client, ClientId},
-	packet::{CreateInstance, Packet, TrustedPacket},
+	handshake::{Handshake, RejectReason},
+	packet::{self, CreateInstance, CreateJoint, Packet, Recipients, TrustedPacket},
+	snapshot::{SentSnapshots, Transform},
 	App, Context,
 };
 
+use heartbeat::{Heartbeat, Shared};
+use interest::{Interest, Relevance};
+use room::Rooms;
+
+/// Limits applied to connections, provided by [`App::network`](crate::App::network).
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkConfig {
+	/// The maximum size of a single packet, in bytes. Connections that send a larger
+	/// packet are closed before it is read into memory.
+	pub max_packet_size: usize,
+	/// The number of packets each client can send per second, on average.
+	/// Packets over this rate are left unread until the client is within its budget.
+	pub max_packets_per_second: f32,
+	/// The number of packets each client can send at once, above its average rate.
+	pub packet_burst: f32,
+	/// How often heartbeats are sent to measure latency and keep the connection alive.
+	pub heartbeat_interval: Duration,
+	/// How long a connection can go without receiving anything before it is closed.
+	pub idle_timeout: Duration,
+	/// The size of the smallest change in position sent to clients, in world units.
+	/// This must be the same on the server and clients.
+	pub position_precision: f32,
+	/// How far in the past remote instances are shown, so that they can move smoothly
+	/// between the updates received for them. Zero applies updates as they arrive.
+	pub interpolation_delay: Duration,
+	/// How long remote instances keep moving when no update has arrived in time.
+	pub max_extrapolation: Duration,
+	/// How far the position (in world units) or rotation (in radians) predicted by a
+	/// client can be from the server's before it is corrected. See [`Context::predict`](crate::Context::predict).
+	pub reconciliation_threshold: f32,
+	/// Whether player instances are only moved by the server, which simulates the inputs
+	/// sent by each client with [`App::simulate`](crate::App::simulate). Updates sent by
+	/// clients for their own player instance are dropped. This is only used by the server.
+	pub server_authoritative: bool,
+	/// The number of packets from a client that can be rejected by
+	/// [`App::validate_packet`](crate::App::validate_packet) before it is disconnected.
+	/// Zero never disconnects clients for it.
+	pub max_rejected_packets: u32,
+	/// Which instances are replicated to each client. See [`Interest`].
+	pub interest: Interest,
+}
+
+impl Default for NetworkConfig {
+	fn default() -> Self {
+		Self {
+			max_packet_size: 1_024 * 1_024,
+			max_packets_per_second: 240.0,
+			packet_burst: 480.0,
+			heartbeat_interval: Duration::from_millis(500),
+			idle_timeout: Duration::from_secs(10),
+			position_precision: 1.0 / 1_024.0,
+			interpolation_delay: Duration::from_millis(100),
+			max_extrapolation: Duration::from_millis(250),
+			reconciliation_threshold: 0.01,
+			server_authoritative: false,
+			max_rejected_packets: 100,
+			interest: Interest::All,
+		}
+	}
+}
+
+/// Why a client left the game.
+#[derive(Debug, Clone, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub enum DisconnectReason {
+	/// The client closed the connection itself.
+	Left,
+	/// Nothing was received within the idle timeout.
+	TimedOut,
+	/// The connection sent a malformed packet or frame.
+	InvalidData,
+	/// The client moved to another room, so it can no longer be seen. See
+	/// [`Context::join_room`](crate::Context::join_room).
+	LeftRoom,
+	/// Too many of the client's packets were rejected by [`App::validate_packet`](crate::App::validate_packet).
+	Rejected,
+	/// Reading from or writing to the connection failed.
+	Error(String),
+}
+
+impl fmt::Display for DisconnectReason {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Left => write!(f, "left the game"),
+			Self::TimedOut => write!(f, "timed out"),
+			Self::InvalidData => write!(f, "sent invalid data"),
+			Self::LeftRoom => write!(f, "moved to another room"),
+			Self::Rejected => write!(f, "sent too many rejected packets"),
+			Self::Error(e) => write!(f, "connection failed: {e}"),
+		}
+	}
+}
+
+impl From<&Error> for DisconnectReason {
+	fn from(e: &Error) -> Self {
+		match e {
+			Error::TimedOut => Self::TimedOut,
+			Error::Packet(packet::Error::Bitcode(..)) => Self::InvalidData,
+			Error::Io(e) | Error::Packet(packet::Error::Io(e))
+				if e.kind() == io::ErrorKind::InvalidData =>
+			{
+				Self::InvalidData
+			}
+			Error::Disconnected(reason) => reason.clone(),
+			e => Self::Error(e.to_string()),
+		}
+	}
+}
+
+/// An error that stopped the connection to the server.
+#[derive(Debug)]
+pub enum Error {
+	/// The connection failed.
+	Io(io::Error),
+	/// A packet could not be read.
+	Packet(packet::Error),
+	/// The server rejected the connection during the handshake.
+	Rejected(RejectReason),
+	/// Nothing was received from the server within the idle timeout.
+	TimedOut,
+	/// The server closed the connection.
+	Disconnected(DisconnectReason),
+	/// The [`Context`] was dropped, so there is nothing left to communicate with.
+	Closed,
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::Io(e) => write!(f, "connection failed: {e}"),
+			Self::Packet(e) => write!(f, "failed to read packet: {e:?}"),
+			Self::Rejected(reason) => write!(f, "rejected by server: {reason}"),
+			Self::TimedOut => write!(f, "connection timed out"),
+			Self::Disconnected(reason) => write!(f, "disconnected by server: {reason}"),
+			Self::Closed => write!(f, "context closed"),
+		}
+	}
+}
+
+impl error::Error for Error {}
+
+impl From<io::Error> for Error {
+	fn from(e: io::Error) -> Self {
+		Self::Io(e)
+	}
+}
+
+impl From<packet::Error> for Error {
+	fn from(e: packet::Error) -> Self {
+		Self::Packet(e)
+	}
+}
+
+impl<T> From<mpsc::SendError<T>> for Error {
+	fn from(_: mpsc::SendError<T>) -> Self {
+		Self::Closed
+	}
+}
+
 pub type Owners = BTreeMap<InstanceId, ClientId>;
 
 /// Hosts a server that can be connected to by clients.
@@ -26,21 +190,58 @@ struct Server<M> {
 	client_rx: mpsc::Receiver<Client>,
 
 	clients: BTreeMap<ClientId, Client>,
+	// connections that have not completed the handshake yet, with when they connected
+	pending: Vec<(Instant, Client)>,
+	// the handshake expected from clients, or sent to the server
+	handshake: Handshake,
+	// the number of models in the drum, which the model ids sent by clients must be below
+	models: usize,
+	network: NetworkConfig,
+	// heartbeats sent to each client, or to the server (as `ClientId::SERVER`)
+	heartbeats: BTreeMap<ClientId, Heartbeat>,
+	shared: Arc<Shared>,
 	// an up-to-date list of all active instances, indexed by their id
 	instances: BTreeMap<InstanceId, CreateInstance>,
+	// an up-to-date list of all active joints, indexed by their id
+	joints: BTreeMap<JointId, CreateJoint>,
 	// represents the owner (client_id) of an instance
 	owners: Owners,
+	// the player instance of each client
+	players: BTreeMap<ClientId, InstanceId>,
+	// the number of packets rejected by `App::validate_packet` for each client
+	rejected: BTreeMap<ClientId, u32>,
+	// the instances sent to each client, when interest management is enabled
+	relevance: Relevance,
+	// the room of every client and instance
+	rooms: Rooms,
+	// clients that failed while being written to, dropped at the end of the tick
+	dropped: Vec<(ClientId, DisconnectReason)>,
+	// the latest quantized transform of every moving instance
+	transforms: BTreeMap<InstanceId, Transform>,
+	// whether a transform has changed since the last snapshot
+	transforms_changed: bool,
+	// the snapshots sent to each client
+	snapshots: BTreeMap<ClientId, SentSnapshots>,
+	// the gravity of the world, if it has been changed at runtime
+	gravity: Option<glam::Vec3>,
 
 	next_instance_id: Arc<AtomicU32>,
+	next_joint_id: Arc<AtomicU32>,
 }
 
 impl<M> Server<M> {
+	#[allow(clippy::too_many_arguments)]
 	pub(crate) fn new(
 		packet_rx: mpsc::Receiver<Packet<M>>,
 		packet_tx: mpsc::Sender<TrustedPacket<M>>,
 		client_tx: mpsc::Sender<Client>,
 		client_rx: mpsc::Receiver<Client>,
 		next_instance_id: Arc<AtomicU32>,
+		next_joint_id: Arc<AtomicU32>,
+		handshake: Handshake,
+		models: usize,
+		network: NetworkConfig,
+		shared: Arc<Shared>,
 	) -> Self {
 		Self {
 			packet_rx,
@@ -48,9 +249,26 @@ impl<M> Server<M> {
 			client_tx,
 			client_rx,
 			clients: BTreeMap::new(),
+			pending: Vec::new(),
+			handshake,
+			models,
+			network,
+			heartbeats: BTreeMap::new(),
+			shared,
 			instances: BTreeMap::new(),
+			joints: BTreeMap::new(),
 			owners: BTreeMap::new(),
+			players: BTreeMap::new(),
+			rejected: BTreeMap::new(),
+			relevance: Relevance::default(),
+			rooms: Rooms::default(),
+			dropped: Vec::new(),
+			transforms: BTreeMap::new(),
+			transforms_changed: false,
+			snapshots: BTreeMap::new(),
+			gravity: None,
 			next_instance_id,
+			next_joint_id,
 		}
 	}
 }
@@ -58,12 +276,88 @@ impl<M> Server<M> {
 impl<M> Context<M> {
 	/// Sends a packet to the server.
 	///
-	/// # Panics
-	///
-	/// Panics if the packet channel has closed, which will only happen if the
-	/// communication with the server has failed.
+	/// Packets sent while disconnected are dropped. See [`App::on_network_error`].
 	pub fn send_packet(&self, packet: Packet<M>) {
-		self.packet_tx.send(packet).unwrap();
+		let _ = self.packet_tx.send(packet);
+	}
+
+	/// Sends a message to a single client, which receives it as a [`Packet::Custom`].
+	#[cfg(feature = "server")]
+	pub fn send_to(&self, client_id: ClientId, message: M) {
+		self.send_message(Recipients::Client(client_id), message);
+	}
+
+	/// Sends a message to a set of clients, which receive it as a [`Packet::Custom`].
+	#[cfg(feature = "server")]
+	pub fn send_to_clients(&self, client_ids: impl IntoIterator<Item = ClientId>, message: M) {
+		self.send_message(
+			Recipients::Clients(client_ids.into_iter().collect()),
+			message,
+		);
+	}
+
+	/// Sends a message to every client except one, such as the client that sent
+	/// the message being forwarded.
+	#[cfg(feature = "server")]
+	pub fn send_to_all_except(&self, client_id: ClientId, message: M) {
+		self.send_message(Recipients::AllExcept(client_id), message);
+	}
+
+	/// Sends a message to the server only, which receives it as a [`Packet::Custom`]
+	/// without forwarding it to other clients.
+	///
+	/// This does nothing on the server.
+	pub fn send_to_server(&self, message: M) {
+		self.send_message(Recipients::Server, message);
+	}
+
+	/// Sends a message to specific recipients. Clients can only send messages
+	/// to [`Recipients::Server`].
+	///
+	/// Messages sent with [`Packet::Custom`] are sent to everyone instead.
+	pub fn send_message(&self, to: Recipients, message: M) {
+		let _ = self.packet_tx.send(Packet::Message { to, message });
+	}
+
+	/// Returns the owner of an instance, which is the only client allowed to update or
+	/// delete it. Instances with no known owner belong to the server.
+	#[must_use]
+	pub fn owner(&self, id: InstanceId) -> ClientId {
+		self.owners.get(&id).copied().unwrap_or(ClientId::SERVER)
+	}
+
+	/// Returns `true` if this client owns an instance. The server owns every instance
+	/// that has not been given to a client.
+	#[must_use]
+	pub fn is_owner(&self, id: InstanceId) -> bool {
+		self.owner(id) == self.client_id.unwrap_or(ClientId::SERVER)
+	}
+
+	/// Gives the ownership of an instance to a client, or back to the server with
+	/// [`ClientId::SERVER`]. Everyone that can see the instance receives a [`Packet::SetOwner`].
+	///
+	/// Only the server can change owners, so clients use [`Self::request_ownership`].
+	#[cfg(feature = "server")]
+	pub fn set_owner(&mut self, id: InstanceId, owner: ClientId) {
+		self.owners.insert(id, owner);
+
+		let _ = self.packet_tx.send(Packet::SetOwner { id, owner });
+	}
+
+	/// Asks the server to give the ownership of an instance to `owner`, such as this
+	/// client to pick up a ball, or [`ClientId::SERVER`] to let go of it. The server
+	/// decides with [`App::approve_ownership`], and a [`Packet::SetOwner`] is received
+	/// if it was approved.
+	///
+	/// On the server, use [`Self::set_owner`] instead.
+	pub fn request_ownership(&self, id: InstanceId, owner: ClientId) {
+		let _ = self.packet_tx.send(Packet::RequestOwnership { id, owner });
+	}
+
+	/// Returns `true` if an instance can be given to a client, which must be connected.
+	#[cfg(feature = "server")]
+	pub(crate) fn can_own(&self, owner: ClientId) -> bool {
+		owner == ClientId::SERVER || self.clients.contains_key(&owner)
 	}
 }
 
@@ -79,22 +373,66 @@ impl InstanceId {
 	}
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, bitcode::Encode, bitcode::Decode)]
+pub struct JointId(u32);
+
+impl JointId {
+	pub const NONE: Self = Self(u32::MAX);
+
+	#[must_use]
+	pub fn new(id: u32) -> Self {
+		Self(id)
+	}
+}
+
+/// Runs the server, or the connection to a remote server if the "server" feature
+/// is not enabled.
+///
+/// # Errors
+///
+/// Returns an error if the connection to the remote server fails or is rejected.
+/// Dropping the [`Context`] stops the server thread without an error.
+#[allow(clippy::too_many_arguments)]
 pub fn run<A: App<M>, M>(
 	packet_tx: mpsc::Sender<TrustedPacket<M>>,
 	packet_rx: mpsc::Receiver<Packet<M>>,
 	next_instance_id: Arc<AtomicU32>,
+	next_joint_id: Arc<AtomicU32>,
+	handshake: Handshake,
+	models: usize,
+	network: NetworkConfig,
+	shared: Arc<Shared>,
 	local: CreateInstance,
-) where
+) -> Result<(), Error>
+where
 	M: bitcode::DecodeOwned + bitcode::Encode + fmt::Debug,
 {
 	let (client_tx, client_rx) = mpsc::channel();
-	let state = Server::new(packet_rx, packet_tx, client_tx, client_rx, next_instance_id);
+	let state = Server::new(
+		packet_rx,
+		packet_tx,
+		client_tx,
+		client_rx,
+		next_instance_id,
+		next_joint_id,
+		handshake,
+		models,
+		network,
+		shared,
+	);
 
 	#[cfg(feature = "server")]
-	Server::run_listener::<A>(&state);
+	Server::run_listener::<A>(&state)?;
 	#[cfg(feature = "server")]
-	Server::run_server(state, local);
+	let result = Server::run_server::<A>(state, local);
 	// Runs the client, which connects to a remote server.
 	#[cfg(all(not(feature = "server"), feature = "client"))]
-	Server::run_client::<A>(state);
+	let result = Server::run_client::<A>(state);
+	#[cfg(not(any(feature = "server", feature = "client")))]
+	let result = Ok(());
+
+	match result {
+		Err(Error::Closed) => Ok(()),
+		result => result,
+	}
 }
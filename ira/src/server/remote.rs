@@ -1,34 +1,95 @@
-use std::{fmt, io::BufReader, io::BufWriter};
+use std::{
+	fmt, io,
+	sync::mpsc,
+	time::{Duration, Instant},
+};
 
-use tracing::info;
+use tracing::{error, info};
 
 use crate::{
 	client::{Client, ClientId},
-	packet::{Packet, TrustedPacket},
+	handshake::HandshakeResponse,
+	packet::{self, Packet, TrustedPacket, UpdateInstance},
+	snapshot::ReceivedSnapshots,
+	transport::Delivery,
 	App,
 };
 
-use super::Server;
+use super::{heartbeat::Heartbeat, Error, Server};
+
+/// Yields while waiting for the server during the handshake, failing once `deadline` passes.
+fn wait_until(deadline: Instant) -> Result<(), Error> {
+	if Instant::now() >= deadline {
+		error!("server did not complete the handshake in time");
+
+		return Err(Error::Io(io::Error::new(
+			io::ErrorKind::TimedOut,
+			"server did not complete the handshake",
+		)));
+	}
+
+	std::thread::yield_now();
+
+	Ok(())
+}
 
 impl<M> Server<M>
 where
 	M: bitcode::DecodeOwned + bitcode::Encode,
 {
-	pub(crate) fn run_client<A: App<M>>(self)
+	/// Connects to the server and completes the handshake, forwarding the
+	/// [`Packet::Connected`] it replies with to the local context.
+	fn connect<A: App<M>>(&self) -> Result<Client, Error>
 	where
 		M: fmt::Debug,
 	{
-		let stream = A::connect();
-		let addr = stream.peer_addr().unwrap();
+		let transport = A::connect()?;
+
+		info!(addr = ?transport.peer_addr(), "connected to server");
+
+		let mut client = Client::new(transport, ClientId::SERVER);
 
-		info!(addr = %addr, "connected to server");
+		client
+			.transport
+			.set_max_frame_size(self.network.max_packet_size);
 
-		let mut client = Client::new(stream, ClientId::SERVER);
+		client.send(&bitcode::encode(&self.handshake), Delivery::Reliable)?;
+
+		// the server may never reply, so give up once the connection would have gone idle
+		let deadline = Instant::now() + self.network.idle_timeout;
+
+		let response = loop {
+			match client.next_packet() {
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => wait_until(deadline)?,
+				data => break bitcode::decode::<HandshakeResponse>(&data?),
+			}
+		};
+
+		match response.map_err(packet::Error::from)? {
+			HandshakeResponse::Accepted => {}
+			HandshakeResponse::Rejected(reason) => {
+				error!(%reason, "server rejected connection");
+
+				return Err(Error::Rejected(reason));
+			}
+		}
 
 		// get client id
-		let packet = TrustedPacket::<M>::read(&mut client).unwrap();
+		let packet = loop {
+			match TrustedPacket::<M>::read(&mut client) {
+				Err(packet::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => {
+					wait_until(deadline)?;
+				}
+				packet => break packet?,
+			}
+		};
 		let Packet::Connected { instance_id } = packet.inner else {
-			panic!("expected SetClientId packet, got {packet:?}");
+			error!(?packet, "expected Connected packet from server");
+
+			return Err(Error::Io(io::Error::new(
+				io::ErrorKind::InvalidData,
+				"expected Connected packet",
+			)));
 		};
 
 		info!(
@@ -38,18 +99,115 @@ where
 
 		client.id = packet.client_id;
 
-		client.stream.set_nonblocking(true).unwrap();
-		self.packet_tx.send(packet).unwrap();
+		self.packet_tx.send(packet)?;
+
+		Ok(client)
+	}
+
+	pub(crate) fn run_client<A: App<M>>(self) -> Result<(), Error>
+	where
+		M: fmt::Debug,
+	{
+		let mut client = self.connect::<A>()?;
+		let mut heartbeat = Heartbeat::new();
+		let mut snapshots = ReceivedSnapshots::default();
 
 		loop {
+			if heartbeat.timed_out(self.network.idle_timeout) {
+				error!("connection to server timed out");
+
+				return Err(Error::TimedOut);
+			}
+
+			if let Some(sequence) = heartbeat.ping(self.network.heartbeat_interval) {
+				let time = self.shared.clock.now().as_micros() as u64;
+
+				Packet::<M>::Ping { sequence, time }
+					.into_trusted(client.id)
+					.write(&mut client)?;
+			}
+
 			// read from local
-			while let Ok(packet) = self.packet_rx.try_recv() {
-				packet.write(&mut client).unwrap();
+			loop {
+				let packet = match self.packet_rx.try_recv() {
+					Ok(packet) => packet,
+					Err(mpsc::TryRecvError::Empty) => break,
+					Err(mpsc::TryRecvError::Disconnected) => return Err(Error::Closed),
+				};
+
+				packet.write(&mut client)?;
+
+				// the server drops the connection once it knows we have left
+				if let Packet::DeleteClient { .. } = packet {
+					info!("left the server");
+
+					return Ok(());
+				}
 			}
 
 			// read from server
-			if let Ok(packet) = TrustedPacket::read(&mut client) {
-				self.packet_tx.send(packet).unwrap();
+			let packet = match TrustedPacket::<M>::read(&mut client) {
+				Ok(packet) => packet,
+				Err(packet::Error::Io(e)) if e.kind() == io::ErrorKind::WouldBlock => continue,
+				Err(e) => {
+					error!(error = ?e, "connection to server failed");
+
+					return Err(e.into());
+				}
+			};
+
+			heartbeat.received();
+
+			match packet.inner {
+				Packet::Ping { sequence, time } => {
+					let latency = heartbeat.rtt().map_or(Duration::ZERO, |rtt| rtt / 2);
+
+					self.shared.clock.sync(Duration::from_micros(time), latency);
+
+					Packet::<M>::Pong { sequence }
+						.into_trusted(client.id)
+						.write(&mut client)?;
+				}
+				Packet::Pong { sequence } => {
+					heartbeat.pong(sequence);
+
+					if let Some(stats) = heartbeat.stats() {
+						self.shared
+							.stats
+							.lock()
+							.unwrap()
+							.insert(ClientId::SERVER, stats);
+					}
+				}
+				Packet::Snapshot {
+					sequence,
+					baseline,
+					ref entries,
+				} => {
+					let Some(changed) = snapshots.decode(sequence, baseline, entries) else {
+						continue;
+					};
+
+					Packet::<M>::AckSnapshot { sequence }
+						.into_trusted(client.id)
+						.write(&mut client)?;
+
+					for (id, transform) in changed {
+						let (position, rotation) =
+							transform.dequantize(self.network.position_precision);
+						let delta = UpdateInstance::transform(position, rotation);
+
+						self.packet_tx.send(
+							Packet::UpdateInstance { id, delta }.into_trusted(ClientId::SERVER),
+						)?;
+					}
+				}
+				Packet::DeleteClient { reason } if packet.client_id == client.id => {
+					error!(%reason, "disconnected by server");
+
+					return Err(Error::Disconnected(reason));
+				}
+				_ => self.packet_tx.send(packet)?,
 			}
 		}
 	}
@@ -0,0 +1,195 @@
+use std::collections::BTreeMap;
+
+use crate::{
+	client::ClientId,
+	game::Context,
+	packet::{Packet, Recipients, TrustedPacket},
+	server::InstanceId,
+};
+
+/// Identifies a room, which partitions the clients and instances of the server.
+///
+/// Clients only see the clients and instances in the same room as them, and only
+/// receive messages sent by clients in the same room. The server sees everything.
+#[derive(
+	Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, bitcode::Encode, bitcode::Decode,
+)]
+pub struct RoomId(u32);
+
+impl RoomId {
+	/// The room every client joins when it connects.
+	pub const DEFAULT: Self = Self(0);
+
+	#[must_use]
+	pub fn new(id: u32) -> Self {
+		Self(id)
+	}
+}
+
+/// The state of a room, changed by the server with [`Context::set_room_state`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub enum RoomState {
+	/// Clients are gathering before the match starts.
+	#[default]
+	Lobby,
+	/// The match has started.
+	Started,
+}
+
+/// The rooms of every client and instance, kept by the server thread.
+#[derive(Default)]
+pub(crate) struct Rooms {
+	clients: BTreeMap<ClientId, RoomId>,
+	instances: BTreeMap<InstanceId, RoomId>,
+	states: BTreeMap<RoomId, RoomState>,
+}
+
+impl Rooms {
+	/// Returns the room of a client, which is [`RoomId::DEFAULT`] until it joins another.
+	pub(crate) fn client(&self, client_id: ClientId) -> RoomId {
+		self.clients.get(&client_id).copied().unwrap_or_default()
+	}
+
+	/// Returns the room of an instance, if it is still known.
+	pub(crate) fn instance(&self, id: InstanceId) -> Option<RoomId> {
+		self.instances.get(&id).copied()
+	}
+
+	pub(crate) fn state(&self, room: RoomId) -> RoomState {
+		self.states.get(&room).copied().unwrap_or_default()
+	}
+
+	pub(crate) fn set_client(&mut self, client_id: ClientId, room: RoomId) {
+		self.clients.insert(client_id, room);
+	}
+
+	pub(crate) fn set_instance(&mut self, id: InstanceId, room: RoomId) {
+		self.instances.insert(id, room);
+	}
+
+	pub(crate) fn set_state(&mut self, room: RoomId, state: RoomState) {
+		self.states.insert(room, state);
+	}
+
+	pub(crate) fn remove_client(&mut self, client_id: ClientId) {
+		self.clients.remove(&client_id);
+	}
+
+	pub(crate) fn remove_instance(&mut self, id: InstanceId) {
+		self.instances.remove(&id);
+	}
+
+	/// Returns the remote clients in a room.
+	pub(crate) fn members(&self, room: RoomId) -> impl Iterator<Item = ClientId> + '_ {
+		self.clients
+			.iter()
+			.filter(move |(id, r)| **r == room && **id != ClientId::SERVER)
+			.map(|(id, _)| *id)
+	}
+
+	/// Returns `true` if a packet should be sent to a client, which is only the case
+	/// if whatever it is about is in the same room as the client.
+	///
+	/// Deleted instances are not known anymore, so their deletion is always sent.
+	pub(crate) fn allows<M>(&self, client_id: ClientId, packet: &TrustedPacket<M>) -> bool {
+		let room = self.client(client_id);
+
+		match &packet.inner {
+			Packet::CreateInstance { id, .. }
+			| Packet::UpdateInstance { id, .. }
+			| Packet::SetOwner { id, .. }
+			| Packet::SetProperty { id, .. } => self.instance(*id).is_none_or(|r| r == room),
+			Packet::CreateJoint { options, .. } => {
+				self.instance(options.instance_a).is_none_or(|r| r == room)
+			}
+			Packet::CreateClient { .. } | Packet::DeleteClient { .. } => {
+				self.client(packet.client_id) == room
+			}
+			Packet::Custom(..) => {
+				packet.client_id == ClientId::SERVER || self.client(packet.client_id) == room
+			}
+			_ => true,
+		}
+	}
+}
+
+impl<M> Context<M> {
+	/// Creates a new room, in the [`RoomState::Lobby`] state. Clients can then be moved
+	/// into it with [`Self::join_room`].
+	#[cfg(feature = "server")]
+	pub fn create_room(&mut self) -> RoomId {
+		self.next_room_id += 1;
+
+		RoomId::new(self.next_room_id)
+	}
+
+	/// Moves a client into a room. It stops seeing the clients and instances of its
+	/// previous room, which are told that it left with
+	/// [`DisconnectReason::LeftRoom`](crate::server::DisconnectReason::LeftRoom), and
+	/// the instances it owned there are given to the server.
+	///
+	/// Moving [`ClientId::SERVER`] sets the room of the instances the server creates
+	/// afterwards, while the server itself keeps seeing every room.
+	#[cfg(feature = "server")]
+	pub fn join_room(&mut self, client_id: ClientId, room: RoomId) {
+		self.rooms.insert(client_id, room);
+
+		let _ = self.packet_tx.send(Packet::JoinRoom { client_id, room });
+	}
+
+	/// Moves a client back into [`RoomId::DEFAULT`].
+	#[cfg(feature = "server")]
+	pub fn leave_room(&mut self, client_id: ClientId) {
+		self.join_room(client_id, RoomId::DEFAULT);
+	}
+
+	/// Changes the state of a room, such as to start the match once everyone has joined.
+	/// The clients in the room receive a [`Packet::Room`] with the new state.
+	#[cfg(feature = "server")]
+	pub fn set_room_state(&mut self, room: RoomId, state: RoomState) {
+		self.room_states.insert(room, state);
+
+		let _ = self.packet_tx.send(Packet::SetRoomState { room, state });
+	}
+
+	/// Sends a message to every client in a room, which receive it as a [`Packet::Custom`].
+	#[cfg(feature = "server")]
+	pub fn send_to_room(&self, room: RoomId, message: M) {
+		self.send_message(Recipients::Room(room), message);
+	}
+
+	/// Returns the room of a client.
+	///
+	/// The server knows the room of every client, while a client only knows its own.
+	#[must_use]
+	pub fn room_of(&self, client_id: ClientId) -> RoomId {
+		self.rooms.get(&client_id).copied().unwrap_or_default()
+	}
+
+	/// Returns the room of this client. On the server, this is the room of the
+	/// instances it creates.
+	#[must_use]
+	pub fn room(&self) -> RoomId {
+		self.room_of(self.client_id.unwrap_or(ClientId::SERVER))
+	}
+
+	/// Returns the state of a room.
+	///
+	/// The server knows the state of every room, while a client only knows the state
+	/// of its own room.
+	#[must_use]
+	pub fn room_state(&self, room: RoomId) -> RoomState {
+		self.room_states.get(&room).copied().unwrap_or_default()
+	}
+
+	/// Returns the clients in a room, not including the server.
+	#[cfg(feature = "server")]
+	#[must_use]
+	pub fn room_members(&self, room: RoomId) -> Vec<ClientId> {
+		self.clients
+			.keys()
+			.copied()
+			.filter(|id| *id != ClientId::SERVER && self.room_of(*id) == room)
+			.collect()
+	}
+}
@@ -0,0 +1,376 @@
+use std::{
+	collections::{BTreeMap, VecDeque},
+	time::{Duration, Instant},
+};
+
+use glam::{Quat, Vec3};
+
+use crate::server::InstanceId;
+
+/// The number of snapshots kept to be used as a baseline, on both the server and client.
+const HISTORY_LEN: usize = 32;
+
+/// How long to wait for a snapshot to be acknowledged before sending it again,
+/// if nothing has changed since.
+const RESEND_INTERVAL: Duration = Duration::from_millis(100);
+
+/// The number of bits used by each of the three smallest quaternion components.
+const ROTATION_BITS: u32 = 10;
+const ROTATION_MAX: f32 = ((1 << ROTATION_BITS) - 1) as f32;
+
+/// Encodes a rotation with the smallest-three method, using 32 bits.
+///
+/// The largest component is dropped and recovered from the others, since the
+/// quaternion is normalized. The other three are each stored in 10 bits.
+///
+/// # Examples
+///
+/// ```rust
+/// use ira::{glam::Quat, snapshot};
+///
+/// let rotation = Quat::from_euler(ira::glam::EulerRot::XYZ, 0.3, -1.2, 2.5);
+/// let decoded = snapshot::decode_rotation(snapshot::encode_rotation(rotation));
+///
+/// assert!(rotation.angle_between(decoded) < 0.01);
+/// ```
+#[must_use]
+pub fn encode_rotation(rotation: Quat) -> u32 {
+	let components = rotation.normalize().to_array();
+	let (largest, _) = components
+		.iter()
+		.enumerate()
+		.fold((0, 0.0), |(index, max), (i, c)| {
+			if c.abs() > max {
+				(i, c.abs())
+			} else {
+				(index, max)
+			}
+		});
+
+	// q and -q are the same rotation, so the dropped component is always positive
+	let sign = components[largest].signum();
+
+	components
+		.iter()
+		.enumerate()
+		.filter(|(i, _)| *i != largest)
+		.fold(largest as u32, |packed, (_, c)| {
+			let c = f32::midpoint(c * sign * std::f32::consts::SQRT_2, 1.0);
+
+			(packed << ROTATION_BITS) | (c.clamp(0.0, 1.0) * ROTATION_MAX).round() as u32
+		})
+}
+
+/// Decodes a rotation encoded with [`encode_rotation`].
+#[must_use]
+pub fn decode_rotation(packed: u32) -> Quat {
+	let largest = (packed >> (ROTATION_BITS * 3)) as usize;
+	let mut components = [0.0; 4];
+	let mut sum = 0.0;
+
+	for (n, i) in (0..4).filter(|i| *i != largest).enumerate() {
+		let shift = ROTATION_BITS * (2 - n as u32);
+		let c = ((packed >> shift) & ((1 << ROTATION_BITS) - 1)) as f32 / ROTATION_MAX;
+		let c = (c * 2.0 - 1.0) / std::f32::consts::SQRT_2;
+
+		components[i] = c;
+		sum += c * c;
+	}
+
+	components[largest] = (1.0 - sum).max(0.0).sqrt();
+
+	Quat::from_array(components).normalize()
+}
+
+/// A position and rotation quantized to integers, as sent in snapshots.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, bitcode::Encode, bitcode::Decode)]
+pub struct Transform {
+	/// The position in units of [`NetworkConfig::position_precision`](crate::server::NetworkConfig::position_precision).
+	pub position: [i32; 3],
+	/// The rotation, encoded with [`encode_rotation`].
+	pub rotation: u32,
+}
+
+impl Transform {
+	/// Quantizes a position and rotation, where `precision` is the size of one
+	/// position unit.
+	#[must_use]
+	pub fn quantize(position: Vec3, rotation: Quat, precision: f32) -> Self {
+		Self {
+			position: (position / precision).round().as_ivec3().to_array(),
+			rotation: encode_rotation(rotation),
+		}
+	}
+
+	/// Returns the position and rotation this was quantized from, up to `precision`.
+	#[must_use]
+	pub fn dequantize(&self, precision: f32) -> (Vec3, Quat) {
+		(
+			glam::IVec3::from_array(self.position).as_vec3() * precision,
+			decode_rotation(self.rotation),
+		)
+	}
+}
+
+/// A changed instance in a [`Packet::Snapshot`](crate::packet::Packet::Snapshot).
+///
+/// If the baseline snapshot contains the instance, the position is relative to
+/// its position in the baseline, otherwise it is absolute.
+#[derive(Debug, Clone, Copy, bitcode::Encode, bitcode::Decode)]
+pub struct Entry {
+	pub id: InstanceId,
+	pub transform: Transform,
+}
+
+/// The transforms of every instance at the time of a snapshot.
+type State = BTreeMap<InstanceId, Transform>;
+
+/// Applies the entries of a snapshot to its baseline, returning the full state.
+#[cfg_attr(feature = "server", allow(dead_code))]
+fn apply(base: &State, entries: &[Entry]) -> State {
+	let mut state = base.clone();
+
+	for entry in entries {
+		let mut transform = entry.transform;
+
+		if let Some(base) = base.get(&entry.id) {
+			for (p, b) in transform.position.iter_mut().zip(base.position) {
+				*p = p.wrapping_add(b);
+			}
+		}
+
+		state.insert(entry.id, transform);
+	}
+
+	state
+}
+
+/// The snapshots sent to a single client, used to encode new snapshots against
+/// the last one it acknowledged.
+#[derive(Default)]
+pub(crate) struct SentSnapshots {
+	next_sequence: u32,
+	acked: Option<(u32, State)>,
+	sent: VecDeque<(u32, State)>,
+	last_sent: Option<Instant>,
+}
+
+impl SentSnapshots {
+	/// Encodes the transforms into a new snapshot, returning its sequence number,
+	/// baseline and entries. Only transforms that differ from the baseline are included.
+	pub(crate) fn encode<'t>(
+		&mut self,
+		transforms: impl Iterator<Item = (&'t InstanceId, &'t Transform)>,
+	) -> (u32, Option<u32>, Vec<Entry>) {
+		let state = transforms
+			.map(|(id, transform)| (*id, *transform))
+			.collect::<State>();
+
+		// the client may no longer have the baseline if none of these have been
+		// acknowledged, so start over from a full snapshot
+		if self.sent.len() == HISTORY_LEN {
+			self.acked = None;
+			self.sent.clear();
+		}
+
+		let empty = State::new();
+		let (baseline, base) = self
+			.acked
+			.as_ref()
+			.map_or((None, &empty), |(sequence, state)| (Some(*sequence), state));
+
+		let entries = state
+			.iter()
+			.filter(|(id, transform)| base.get(id) != Some(transform))
+			.map(|(id, transform)| {
+				let mut transform = *transform;
+
+				if let Some(base) = base.get(id) {
+					for (p, b) in transform.position.iter_mut().zip(base.position) {
+						*p = p.wrapping_sub(b);
+					}
+				}
+
+				Entry { id: *id, transform }
+			})
+			.collect::<Vec<_>>();
+
+		let sequence = self.next_sequence;
+
+		self.next_sequence = self.next_sequence.wrapping_add(1);
+
+		self.last_sent = Some(Instant::now());
+
+		// instances that no longer exist are left out, which the client
+		// cannot tell apart since they are never referred to again
+		self.sent.push_back((sequence, state));
+
+		(sequence, baseline, entries)
+	}
+
+	/// Returns `true` if the latest snapshot has not been acknowledged in a while,
+	/// so it may have been lost.
+	pub(crate) fn needs_resend(&self) -> bool {
+		!self.sent.is_empty()
+			&& self
+				.last_sent
+				.is_some_and(|last| last.elapsed() >= RESEND_INTERVAL)
+	}
+
+	/// Marks a snapshot as received by the client, making it the new baseline.
+	pub(crate) fn ack(&mut self, sequence: u32) {
+		let Some(index) = self.sent.iter().position(|(s, _)| *s == sequence) else {
+			return;
+		};
+
+		self.acked = self.sent.drain(..=index).next_back();
+	}
+}
+
+/// The snapshots received from the server, used to decode new snapshots.
+#[derive(Default)]
+#[cfg_attr(feature = "server", allow(dead_code))]
+pub(crate) struct ReceivedSnapshots {
+	latest: Option<u32>,
+	received: VecDeque<(u32, State)>,
+	/// The transforms last given to the [`Context`](crate::Context).
+	current: State,
+}
+
+impl ReceivedSnapshots {
+	/// Decodes a snapshot, returning the transforms that have changed since the
+	/// last snapshot that was decoded.
+	///
+	/// Returns `None` if the snapshot is older than the latest one, or if its
+	/// baseline is no longer known, in which case it should not be acknowledged.
+	#[cfg_attr(feature = "server", allow(dead_code))]
+	pub(crate) fn decode(
+		&mut self,
+		sequence: u32,
+		baseline: Option<u32>,
+		entries: &[Entry],
+	) -> Option<Vec<(InstanceId, Transform)>> {
+		if self.latest.is_some_and(|latest| latest >= sequence) {
+			return None;
+		}
+
+		let empty = State::new();
+		let base = match baseline {
+			None => &empty,
+			Some(baseline) => self
+				.received
+				.iter()
+				.find_map(|(s, state)| (*s == baseline).then_some(state))?,
+		};
+
+		let state = apply(base, entries);
+		let changed = state
+			.iter()
+			.filter(|(id, transform)| self.current.get(id) != Some(transform))
+			.map(|(id, transform)| (*id, *transform))
+			.collect::<Vec<_>>();
+
+		self.current.extend(changed.iter().copied());
+		self.latest = Some(sequence);
+
+		if self.received.len() == HISTORY_LEN {
+			self.received.pop_front();
+		}
+
+		self.received.push_back((sequence, state));
+
+		Some(changed)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn transform(x: i32) -> Transform {
+		Transform {
+			position: [x, -x, x * 2],
+			rotation: encode_rotation(Quat::IDENTITY),
+		}
+	}
+
+	fn state(transforms: &[(u32, i32)]) -> State {
+		transforms
+			.iter()
+			.map(|(id, x)| (InstanceId::new(*id), transform(*x)))
+			.collect()
+	}
+
+	/// Encodes the state and decodes it on the other end, acknowledging it.
+	fn send(
+		sent: &mut SentSnapshots,
+		received: &mut ReceivedSnapshots,
+		state: &State,
+	) -> Option<Vec<(InstanceId, Transform)>> {
+		let (sequence, baseline, entries) = sent.encode(state.iter());
+		let changed = received.decode(sequence, baseline, &entries)?;
+
+		sent.ack(sequence);
+
+		Some(changed)
+	}
+
+	#[test]
+	fn deltas_round_trip() {
+		let mut sent = SentSnapshots::default();
+		let mut received = ReceivedSnapshots::default();
+
+		let first = state(&[(0, 1), (1, 2), (2, 3)]);
+
+		assert_eq!(
+			send(&mut sent, &mut received, &first),
+			Some(first.clone().into_iter().collect())
+		);
+
+		// only the changed instance is sent, relative to the acknowledged baseline
+		let second = state(&[(0, 1), (1, -5), (2, 3)]);
+		let (sequence, baseline, entries) = sent.encode(second.iter());
+
+		assert_eq!(baseline, Some(0));
+		assert_eq!(entries.len(), 1);
+		assert_ne!(entries[0].transform, transform(-5));
+
+		assert_eq!(
+			received.decode(sequence, baseline, &entries),
+			Some(vec![(InstanceId::new(1), transform(-5))])
+		);
+		assert_eq!(received.current, second);
+	}
+
+	#[test]
+	fn lost_snapshots_are_recovered_from() {
+		let mut sent = SentSnapshots::default();
+		let mut received = ReceivedSnapshots::default();
+
+		send(&mut sent, &mut received, &state(&[(0, 1), (1, 2)]));
+
+		// this one is lost, so it never becomes the baseline
+		sent.encode(state(&[(0, 4), (1, 2)]).iter());
+
+		let latest = state(&[(0, 4), (1, 7)]);
+		let (sequence, baseline, entries) = sent.encode(latest.iter());
+
+		assert_eq!(baseline, Some(0));
+		assert!(received.decode(sequence, baseline, &entries).is_some());
+		assert_eq!(received.current, latest);
+	}
+
+	#[test]
+	fn old_snapshots_and_unknown_baselines_are_ignored() {
+		let mut sent = SentSnapshots::default();
+		let mut received = ReceivedSnapshots::default();
+
+		let (old, baseline, old_entries) = sent.encode(state(&[(0, 1)]).iter());
+		let (new, _, new_entries) = sent.encode(state(&[(0, 2)]).iter());
+
+		assert!(received.decode(new, baseline, &new_entries).is_some());
+		assert_eq!(received.decode(old, baseline, &old_entries), None);
+		assert_eq!(received.decode(new + 1, Some(old), &new_entries), None);
+		assert_eq!(received.current, state(&[(0, 2)]));
+	}
+}
@@ -0,0 +1,125 @@
+use std::{
+	io,
+	sync::mpsc::{self, TryRecvError},
+};
+
+use super::{Listener, Transport};
+
+/// An in-process transport backed by channels.
+///
+/// This is useful for running a client and server in the same process without
+/// any sockets, such as in tests.
+///
+/// # Examples
+///
+/// ```rust
+/// use ira::{
+///     client::{Client, ClientId},
+///     packet::Packet,
+///     transport::{channel, Listener},
+/// };
+///
+/// let (mut listener, connector) = channel::listener();
+///
+/// let mut client = Client::new(Box::new(connector.connect()), ClientId::SERVER);
+/// let mut server = Client::new(listener.accept().unwrap(), ClientId::SERVER.next());
+///
+/// Packet::new(42u32).write(&mut client).unwrap();
+///
+/// let Packet::Custom(message) = Packet::<u32>::read(&mut server).unwrap() else {
+///     panic!("expected a custom packet");
+/// };
+///
+/// assert_eq!(message, 42);
+/// ```
+pub struct ChannelTransport {
+	tx: mpsc::Sender<Vec<u8>>,
+	rx: mpsc::Receiver<Vec<u8>>,
+	max_frame_size: usize,
+}
+
+/// Creates two connected transports.
+#[must_use]
+pub fn pair() -> (ChannelTransport, ChannelTransport) {
+	let (a_tx, b_rx) = mpsc::channel();
+	let (b_tx, a_rx) = mpsc::channel();
+
+	(
+		ChannelTransport {
+			tx: a_tx,
+			rx: a_rx,
+			max_frame_size: usize::MAX,
+		},
+		ChannelTransport {
+			tx: b_tx,
+			rx: b_rx,
+			max_frame_size: usize::MAX,
+		},
+	)
+}
+
+/// Creates a listener, and a connector that can be used to connect to it.
+#[must_use]
+pub fn listener() -> (ChannelListener, ChannelConnector) {
+	let (tx, rx) = mpsc::channel();
+
+	(ChannelListener { rx }, ChannelConnector { tx })
+}
+
+impl Transport for ChannelTransport {
+	fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+		self.tx
+			.send(frame.to_vec())
+			.map_err(|_| io::ErrorKind::BrokenPipe.into())
+	}
+
+	fn recv(&mut self) -> io::Result<Vec<u8>> {
+		let frame = self.rx.try_recv().map_err(|e| match e {
+			TryRecvError::Empty => io::Error::from(io::ErrorKind::WouldBlock),
+			TryRecvError::Disconnected => io::ErrorKind::UnexpectedEof.into(),
+		})?;
+
+		if frame.len() > self.max_frame_size {
+			return Err(io::ErrorKind::InvalidData.into());
+		}
+
+		Ok(frame)
+	}
+
+	fn set_max_frame_size(&mut self, max: usize) {
+		self.max_frame_size = max;
+	}
+}
+
+/// Accepts in-process connections made with a [`ChannelConnector`].
+pub struct ChannelListener {
+	rx: mpsc::Receiver<ChannelTransport>,
+}
+
+impl Listener for ChannelListener {
+	fn accept(&mut self) -> io::Result<Box<dyn Transport>> {
+		match self.rx.recv() {
+			Ok(transport) => Ok(Box::new(transport)),
+			Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+		}
+	}
+}
+
+/// Connects to a [`ChannelListener`].
+#[derive(Clone)]
+pub struct ChannelConnector {
+	tx: mpsc::Sender<ChannelTransport>,
+}
+
+impl ChannelConnector {
+	/// Connects to the listener, returning the client end of the connection.
+	///
+	/// If the listener has been dropped, the returned transport is already closed.
+	#[must_use]
+	pub fn connect(&self) -> ChannelTransport {
+		let (client, server) = pair();
+		let _ = self.tx.send(server);
+
+		client
+	}
+}
@@ -0,0 +1,89 @@
+//! Transports carry encoded packets between the client and server.
+//!
+//! A [`Transport`] is a single connection that sends and receives whole frames,
+//! and a [`Listener`] accepts new connections on the server. Ira provides
+//! [`tcp`], [`udp`] and in-process [`channel`] transports, and others can be added by
+//! implementing these traits and returning them from [`App::connect`](crate::App::connect)
+//! and [`App::listen`](crate::App::listen).
+
+pub mod channel;
+pub mod tcp;
+pub mod udp;
+
+use std::{io, net::SocketAddr};
+
+pub use channel::{ChannelConnector, ChannelListener, ChannelTransport};
+pub use tcp::TcpTransport;
+pub use udp::{UdpListener, UdpTransport};
+
+/// How a frame should be delivered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+	/// The frame must arrive, in order with all other reliable frames.
+	Reliable,
+	/// The frame may be dropped, and frames older than the newest one received
+	/// are discarded. This is used for frequent updates that are quickly superseded.
+	Unreliable,
+}
+
+/// A connection that sends and receives whole frames.
+#[allow(unused_variables)]
+pub trait Transport: Send {
+	/// Sends a single frame.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the connection has been closed or the frame could not be sent.
+	fn send(&mut self, frame: &[u8]) -> io::Result<()>;
+
+	/// Sends a single frame that may be dropped or superseded by a newer one.
+	///
+	/// By default, this sends the frame reliably.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the connection has been closed or the frame could not be sent.
+	fn send_unreliable(&mut self, frame: &[u8]) -> io::Result<()> {
+		self.send(frame)
+	}
+
+	/// Receives the next frame without blocking.
+	///
+	/// # Errors
+	///
+	/// Returns [`io::ErrorKind::WouldBlock`] if no complete frame is available yet,
+	/// or another error if the connection has been closed.
+	fn recv(&mut self) -> io::Result<Vec<u8>>;
+
+	/// Sets the maximum size of a frame that can be received, in bytes.
+	///
+	/// Receiving a larger frame must fail with [`io::ErrorKind::InvalidData`],
+	/// ideally before the frame is read into memory.
+	///
+	/// By default, this does nothing, so frames of any size are received.
+	fn set_max_frame_size(&mut self, max: usize) {}
+
+	/// Returns the address of the remote end of the connection, if it has one.
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		None
+	}
+}
+
+/// Accepts incoming connections on the server.
+pub trait Listener: Send {
+	/// Blocks until a new connection is accepted.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the connection could not be accepted. The server keeps
+	/// accepting connections after a [`WouldBlock`](io::ErrorKind::WouldBlock),
+	/// [`Interrupted`](io::ErrorKind::Interrupted) or
+	/// [`ConnectionAborted`](io::ErrorKind::ConnectionAborted) error, and stops
+	/// listening after any other.
+	fn accept(&mut self) -> io::Result<Box<dyn Transport>>;
+
+	/// Returns the address the listener is bound to, if it has one.
+	fn local_addr(&self) -> Option<SocketAddr> {
+		None
+	}
+}
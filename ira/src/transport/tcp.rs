@@ -0,0 +1,251 @@
+use std::{
+	io::{self, Read, Write},
+	mem,
+	net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use super::{Listener, Transport};
+
+/// The number of bytes that can be waiting to be written to a connection before it is
+/// closed, since the peer is not reading them fast enough.
+pub const MAX_UNSENT: usize = 16 * 1_024 * 1_024;
+
+/// A frame that is being read.
+pub struct PartialPacket {
+	pub(crate) len: Option<usize>,
+	pub(crate) data: Vec<u8>,
+	pub(crate) offset: usize,
+}
+
+impl Default for PartialPacket {
+	fn default() -> Self {
+		Self {
+			len: None,
+			data: vec![0; 4],
+			offset: 0,
+		}
+	}
+}
+
+/// A transport over a TCP stream, where each frame is prefixed by its length.
+pub struct TcpTransport {
+	stream: TcpStream,
+	max_frame_size: usize,
+
+	/// Data that the stream did not accept yet, which is written before anything else.
+	unsent: Vec<u8>,
+
+	/// A partial packet that is being read.
+	///
+	/// When reading the next packet, check if this is `Some`. If it is,
+	/// read the remaining data into the partial packet.
+	next_packet: PartialPacket,
+}
+
+impl TcpTransport {
+	/// Creates a new transport from a connected stream.
+	///
+	/// # Errors
+	///
+	/// Returns an error if the stream cannot be made non-blocking.
+	pub fn new(stream: TcpStream) -> io::Result<Self> {
+		stream.set_nonblocking(true)?;
+
+		Ok(Self {
+			stream,
+			max_frame_size: usize::MAX,
+			unsent: Vec::new(),
+			next_packet: PartialPacket::default(),
+		})
+	}
+
+	/// Connects to a server.
+	///
+	/// # Errors
+	///
+	/// See [`TcpStream::connect`].
+	pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		Self::new(TcpStream::connect(addr)?)
+	}
+
+	/// Reads from the stream into `buf`, returning the number of bytes read.
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		match self.stream.read(buf)? {
+			0 => Err(io::ErrorKind::UnexpectedEof.into()),
+			n => Ok(n),
+		}
+	}
+
+	/// Writes as much of the unsent data as the stream accepts without blocking. The
+	/// rest is written by later calls to [`Transport::send`] and [`Transport::recv`].
+	fn flush(&mut self) -> io::Result<()> {
+		while !self.unsent.is_empty() {
+			match self.stream.write(&self.unsent) {
+				Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+				Ok(n) => {
+					self.unsent.drain(..n);
+				}
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+				Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+				Err(e) => return Err(e),
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Transport for TcpTransport {
+	/// Queues the frame and writes as much of it as possible without blocking.
+	///
+	/// Fails if more than [`MAX_UNSENT`] bytes are still waiting to be written
+	/// afterwards, in which case the connection should be closed.
+	fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+		self.unsent
+			.extend_from_slice(&(frame.len() as u32).to_le_bytes());
+		self.unsent.extend_from_slice(frame);
+		self.flush()?;
+
+		if self.unsent.len() > MAX_UNSENT {
+			return Err(io::Error::other(format!(
+				"{} unsent bytes exceed the maximum",
+				self.unsent.len()
+			)));
+		}
+
+		Ok(())
+	}
+
+	/// Writes any unsent data, then tries to complete the current packet, returning
+	/// the packet data if it completes.
+	fn recv(&mut self) -> io::Result<Vec<u8>> {
+		self.flush()?;
+
+		let mut partial = mem::take(&mut self.next_packet);
+
+		let len = if let Some(len) = partial.len {
+			len
+		} else {
+			while partial.offset < 4 {
+				let n = match self.read(&mut partial.data[partial.offset..4]) {
+					Ok(n) => n,
+					Err(e) => {
+						self.next_packet = partial;
+						return Err(e);
+					}
+				};
+
+				partial.offset += n;
+			}
+
+			let len = u32::from_le_bytes([
+				partial.data[0],
+				partial.data[1],
+				partial.data[2],
+				partial.data[3],
+			]) as usize;
+
+			if len > self.max_frame_size {
+				return Err(io::Error::new(
+					io::ErrorKind::InvalidData,
+					format!("frame of {len} bytes exceeds the maximum size"),
+				));
+			}
+
+			partial.len = Some(len);
+			partial.data.resize(len, 0);
+			partial.offset = 0;
+
+			len
+		};
+
+		while partial.offset < len {
+			let n = match self.read(&mut partial.data[partial.offset..len]) {
+				Ok(n) => n,
+				Err(e) => {
+					self.next_packet = partial;
+					return Err(e);
+				}
+			};
+
+			partial.offset += n;
+		}
+
+		Ok(partial.data)
+	}
+
+	fn set_max_frame_size(&mut self, max: usize) {
+		self.max_frame_size = max;
+	}
+
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		self.stream.peer_addr().ok()
+	}
+}
+
+impl Listener for TcpListener {
+	fn accept(&mut self) -> io::Result<Box<dyn Transport>> {
+		let (stream, _) = TcpListener::accept(self)?;
+
+		Ok(Box::new(TcpTransport::new(stream)?))
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		TcpListener::local_addr(self).ok()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Connects a client to a server over loopback, returning both ends.
+	fn connect() -> (TcpTransport, TcpTransport) {
+		let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+		let client = TcpTransport::connect(listener.local_addr().unwrap()).unwrap();
+		let (stream, _) = listener.accept().unwrap();
+
+		(client, TcpTransport::new(stream).unwrap())
+	}
+
+	/// Receives the next frame, flushing the other end while waiting.
+	fn recv(from: &mut TcpTransport, to: &mut TcpTransport) -> Vec<u8> {
+		loop {
+			match to.recv() {
+				Ok(frame) => return frame,
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+					if let Err(e) = from.recv() {
+						assert_eq!(e.kind(), io::ErrorKind::WouldBlock);
+					}
+				}
+				Err(e) => panic!("{e}"),
+			}
+		}
+	}
+
+	#[test]
+	fn frames_larger_than_the_socket_buffer_are_sent_without_blocking() {
+		let (mut client, mut server) = connect();
+		let frame = (0..8 * 1_024 * 1_024).map(|i| i as u8).collect::<Vec<_>>();
+
+		client.send(&frame).unwrap();
+		client.send(b"after").unwrap();
+		assert!(!client.unsent.is_empty());
+
+		assert_eq!(recv(&mut client, &mut server), frame);
+		assert_eq!(recv(&mut client, &mut server), b"after");
+
+		server.send(b"reply").unwrap();
+		assert_eq!(recv(&mut server, &mut client), b"reply");
+	}
+
+	#[test]
+	fn connection_is_closed_when_the_peer_stops_reading() {
+		let (_client, mut server) = connect();
+		let frame = vec![0; 1_024 * 1_024];
+
+		let closed = (0..MAX_UNSENT / frame.len() * 4).any(|_| server.send(&frame).is_err());
+
+		assert!(closed);
+	}
+}
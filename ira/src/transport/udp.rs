@@ -0,0 +1,727 @@
+//! A transport over UDP, with a reliable-ordered and an unreliable-sequenced channel.
+//!
+//! Every datagram starts with a one-byte kind. Messages on both channels are split
+//! into fragments that fit within [`MTU`], each carrying a sequence number and its
+//! position within the message:
+//!
+//! - Reliable fragments each get their own sequence number, are acknowledged by the
+//!   receiver, and are resent after [`RESEND_TIMEOUT`] until they are. The receiver
+//!   buffers them and delivers messages strictly in order.
+//! - Unreliable fragments share the sequence number of their message. The receiver
+//!   only delivers a message if all of its fragments arrive before a newer message,
+//!   and drops anything older than the last delivered message.
+
+use std::{
+	collections::{hash_map::Entry, BTreeMap, HashMap, VecDeque},
+	io,
+	net::{SocketAddr, ToSocketAddrs, UdpSocket},
+	sync::{
+		mpsc::{self, TryRecvError, TrySendError},
+		Arc,
+	},
+	time::{Duration, Instant},
+};
+
+use tracing::{debug, info, warn};
+
+use super::{Listener, Transport};
+
+/// The maximum size of a single datagram, including its header.
+///
+/// This is comfortably below the MTU of most networks, so datagrams are not
+/// fragmented by IP.
+pub const MTU: usize = 1_200;
+/// How long to wait for an acknowledgement before resending a reliable fragment.
+pub const RESEND_TIMEOUT: Duration = Duration::from_millis(100);
+/// How many times a reliable fragment is resent before the connection is closed.
+pub const MAX_RESENDS: u32 = 50;
+/// The number of reliable fragments that can be waiting for an acknowledgement before
+/// the connection is closed, since the peer is not acknowledging them fast enough.
+pub const MAX_UNACKED: usize = 16 * 1_024;
+/// How long to wait for the server to accept a connection.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The number of reliable fragments that can be received ahead of the next one to be
+/// delivered. Fragments further ahead are ignored until the gap is filled.
+pub const RELIABLE_WINDOW: u32 = 1_024;
+/// The number of datagrams that can be queued for a connection by a [`UdpListener`]
+/// before new ones are dropped.
+pub const RECV_QUEUE_LEN: usize = 1_024;
+
+/// kind (1) + sequence (4) + fragment index (2) + fragment count (2)
+const HEADER_LEN: usize = 9;
+const FRAGMENT_LEN: usize = MTU - HEADER_LEN;
+
+const CONNECT: u8 = 0;
+const RELIABLE: u8 = 1;
+const UNRELIABLE: u8 = 2;
+const ACK: u8 = 3;
+const DISCONNECT: u8 = 4;
+
+/// A fragment of a message.
+struct Fragment<'d> {
+	sequence: u32,
+	index: u16,
+	count: u16,
+	data: &'d [u8],
+}
+
+impl<'d> Fragment<'d> {
+	fn parse(datagram: &'d [u8]) -> Option<Self> {
+		if datagram.len() < HEADER_LEN {
+			return None;
+		}
+
+		let fragment = Self {
+			sequence: u32::from_le_bytes(datagram[1..5].try_into().ok()?),
+			index: u16::from_le_bytes(datagram[5..7].try_into().ok()?),
+			count: u16::from_le_bytes(datagram[7..9].try_into().ok()?),
+			data: &datagram[HEADER_LEN..],
+		};
+
+		(fragment.index < fragment.count).then_some(fragment)
+	}
+
+	fn encode(kind: u8, sequence: u32, index: u16, count: u16, data: &[u8]) -> Vec<u8> {
+		let mut datagram = Vec::with_capacity(HEADER_LEN + data.len());
+
+		datagram.push(kind);
+		datagram.extend_from_slice(&sequence.to_le_bytes());
+		datagram.extend_from_slice(&index.to_le_bytes());
+		datagram.extend_from_slice(&count.to_le_bytes());
+		datagram.extend_from_slice(data);
+		datagram
+	}
+}
+
+/// Splits a frame into fragments of at most [`FRAGMENT_LEN`] bytes.
+fn fragments(frame: &[u8]) -> io::Result<(u16, impl Iterator<Item = &[u8]>)> {
+	let count = frame.len().div_ceil(FRAGMENT_LEN).max(1);
+	let count = u16::try_from(count).map_err(|_| {
+		io::Error::new(
+			io::ErrorKind::InvalidInput,
+			"frame is too large to be fragmented",
+		)
+	})?;
+
+	// an empty frame is still sent as a single empty fragment
+	let chunks = frame.chunks(FRAGMENT_LEN);
+	let empty = frame.is_empty().then_some(&frame[..0]);
+
+	Ok((count, chunks.chain(empty)))
+}
+
+/// Sends a datagram to `peer`, or to the connected address if `connected` is `true`.
+fn send_datagram(
+	socket: &UdpSocket,
+	connected: bool,
+	peer: SocketAddr,
+	datagram: &[u8],
+) -> io::Result<()> {
+	let result = if connected {
+		socket.send(datagram)
+	} else {
+		socket.send_to(datagram, peer)
+	};
+
+	match result {
+		Ok(..) => Ok(()),
+		// the datagram is dropped, which is allowed by UDP
+		Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(()),
+		Err(e) => Err(e),
+	}
+}
+
+/// Where datagrams for a connection come from.
+enum Inbound {
+	/// A socket connected to the server.
+	Socket(Arc<UdpSocket>),
+	/// Datagrams forwarded by a [`UdpListener`].
+	Channel(mpsc::Receiver<Vec<u8>>),
+}
+
+impl Inbound {
+	fn recv(&mut self) -> io::Result<Vec<u8>> {
+		match self {
+			Self::Socket(socket) => {
+				let mut buf = vec![0; MTU];
+				let n = socket.recv(&mut buf)?;
+
+				buf.truncate(n);
+				Ok(buf)
+			}
+			Self::Channel(rx) => rx.try_recv().map_err(|e| match e {
+				TryRecvError::Empty => io::ErrorKind::WouldBlock.into(),
+				TryRecvError::Disconnected => io::ErrorKind::UnexpectedEof.into(),
+			}),
+		}
+	}
+}
+
+/// A single connection over UDP.
+pub struct UdpTransport {
+	socket: Arc<UdpSocket>,
+	peer: SocketAddr,
+	inbound: Inbound,
+
+	next_reliable: u32,
+	next_unreliable: u32,
+	/// Reliable datagrams that have not been acknowledged yet, with when they were last
+	/// sent and how many times they have been resent.
+	unacked: BTreeMap<u32, (Instant, u32, Vec<u8>)>,
+
+	/// The sequence number of the next reliable fragment to deliver.
+	expected_reliable: u32,
+	/// Reliable fragments received out of order.
+	pending_reliable: BTreeMap<u32, (u16, u16, Vec<u8>)>,
+	/// The reliable message being assembled from in-order fragments.
+	reliable_message: Vec<u8>,
+
+	/// The sequence number of the oldest unreliable message that can still be delivered.
+	expected_unreliable: u32,
+	/// The unreliable message being assembled, with its sequence number and fragments.
+	unreliable_message: Option<(u32, Vec<Option<Vec<u8>>>)>,
+
+	/// Messages that are ready to be received.
+	ready: VecDeque<Vec<u8>>,
+	max_frame_size: usize,
+	closed: bool,
+}
+
+impl UdpTransport {
+	fn new(socket: Arc<UdpSocket>, peer: SocketAddr, inbound: Inbound) -> Self {
+		Self {
+			socket,
+			peer,
+			inbound,
+			next_reliable: 0,
+			next_unreliable: 0,
+			unacked: BTreeMap::new(),
+			expected_reliable: 0,
+			pending_reliable: BTreeMap::new(),
+			reliable_message: Vec::new(),
+			expected_unreliable: 0,
+			unreliable_message: None,
+			ready: VecDeque::new(),
+			max_frame_size: usize::MAX,
+			closed: false,
+		}
+	}
+
+	/// Connects to a server listening with a [`UdpListener`].
+	///
+	/// # Errors
+	///
+	/// Returns [`io::ErrorKind::TimedOut`] if the server does not accept the
+	/// connection within [`CONNECT_TIMEOUT`], or any error from the socket.
+	pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		let peer = addr
+			.to_socket_addrs()?
+			.next()
+			.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address provided"))?;
+
+		let local: SocketAddr = if peer.is_ipv4() {
+			([0, 0, 0, 0], 0).into()
+		} else {
+			([0; 16], 0).into()
+		};
+
+		let socket = UdpSocket::bind(local)?;
+
+		socket.connect(peer)?;
+		socket.set_read_timeout(Some(RESEND_TIMEOUT))?;
+
+		let start = Instant::now();
+		let mut buf = [0; MTU];
+
+		loop {
+			if start.elapsed() >= CONNECT_TIMEOUT {
+				return Err(io::ErrorKind::TimedOut.into());
+			}
+
+			socket.send(&[CONNECT])?;
+
+			match socket.recv(&mut buf) {
+				Ok(n) if n > 0 && buf[0] == CONNECT => break,
+				Ok(..) => {}
+				Err(e)
+					if matches!(
+						e.kind(),
+						io::ErrorKind::WouldBlock
+							| io::ErrorKind::TimedOut
+							| io::ErrorKind::ConnectionRefused
+					) => {}
+				Err(e) => return Err(e),
+			}
+		}
+
+		socket.set_read_timeout(None)?;
+		socket.set_nonblocking(true)?;
+
+		let socket = Arc::new(socket);
+
+		Ok(Self::new(
+			Arc::clone(&socket),
+			peer,
+			Inbound::Socket(socket),
+		))
+	}
+
+	fn send_datagram(&self, datagram: &[u8]) -> io::Result<()> {
+		let connected = matches!(self.inbound, Inbound::Socket(..));
+
+		send_datagram(&self.socket, connected, self.peer, datagram)
+	}
+
+	/// Resends reliable fragments that have not been acknowledged in time, failing if
+	/// one has already been resent [`MAX_RESENDS`] times.
+	fn resend(&mut self) -> io::Result<()> {
+		let connected = matches!(self.inbound, Inbound::Socket(..));
+		let now = Instant::now();
+
+		for (sent, resends, datagram) in self.unacked.values_mut() {
+			if now.duration_since(*sent) >= RESEND_TIMEOUT {
+				if *resends >= MAX_RESENDS {
+					return Err(io::Error::new(
+						io::ErrorKind::TimedOut,
+						"reliable fragment was not acknowledged",
+					));
+				}
+
+				*sent = now;
+				*resends += 1;
+				send_datagram(&self.socket, connected, self.peer, datagram)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	fn handle(&mut self, datagram: &[u8]) -> io::Result<()> {
+		let Some(&kind) = datagram.first() else {
+			return Ok(());
+		};
+
+		match kind {
+			// the server acknowledges every connect attempt, so this is a duplicate
+			CONNECT => {}
+			DISCONNECT => {
+				self.closed = true;
+			}
+			ACK => {
+				if let Some(sequence) = datagram
+					.get(1..5)
+					.and_then(|b| b.try_into().ok())
+					.map(u32::from_le_bytes)
+				{
+					self.unacked.remove(&sequence);
+				}
+			}
+			RELIABLE => {
+				let Some(fragment) = Fragment::parse(datagram) else {
+					return Ok(());
+				};
+
+				let mut ack = [ACK; 5];
+				ack[1..].copy_from_slice(&fragment.sequence.to_le_bytes());
+				self.send_datagram(&ack)?;
+
+				if fragment.sequence >= self.expected_reliable + RELIABLE_WINDOW {
+					return Ok(());
+				}
+
+				if fragment.sequence >= self.expected_reliable {
+					self.pending_reliable.entry(fragment.sequence).or_insert((
+						fragment.index,
+						fragment.count,
+						fragment.data.to_vec(),
+					));
+				}
+
+				while let Some((index, count, data)) =
+					self.pending_reliable.remove(&self.expected_reliable)
+				{
+					self.expected_reliable += 1;
+
+					if self.reliable_message.len() + data.len() > self.max_frame_size {
+						return Err(io::Error::new(
+							io::ErrorKind::InvalidData,
+							"frame exceeds the maximum size",
+						));
+					}
+
+					self.reliable_message.extend_from_slice(&data);
+
+					if index + 1 == count {
+						self.ready
+							.push_back(std::mem::take(&mut self.reliable_message));
+					}
+				}
+			}
+			UNRELIABLE => {
+				let Some(fragment) = Fragment::parse(datagram) else {
+					return Ok(());
+				};
+
+				if fragment.sequence < self.expected_unreliable {
+					return Ok(());
+				}
+
+				if (usize::from(fragment.count) - 1) * FRAGMENT_LEN > self.max_frame_size {
+					return Err(io::Error::new(
+						io::ErrorKind::InvalidData,
+						"frame exceeds the maximum size",
+					));
+				}
+
+				match &self.unreliable_message {
+					Some((sequence, _)) if *sequence > fragment.sequence => return Ok(()),
+					Some((sequence, _)) if *sequence == fragment.sequence => {}
+					// a newer message replaces the one being assembled
+					_ => {
+						self.unreliable_message =
+							Some((fragment.sequence, vec![None; usize::from(fragment.count)]));
+					}
+				}
+
+				let Some((_, parts)) = &mut self.unreliable_message else {
+					return Ok(());
+				};
+
+				if parts.len() != usize::from(fragment.count) {
+					return Ok(());
+				}
+
+				if let Some(part) = parts.get_mut(usize::from(fragment.index)) {
+					*part = Some(fragment.data.to_vec());
+				}
+
+				if parts.iter().all(Option::is_some) {
+					let message = parts.drain(..).flatten().flatten().collect();
+
+					self.ready.push_back(message);
+					self.expected_unreliable = fragment.sequence + 1;
+					self.unreliable_message = None;
+				}
+			}
+			_ => {
+				debug!(kind, peer = %self.peer, "received datagram of unknown kind");
+			}
+		}
+
+		Ok(())
+	}
+}
+
+impl Transport for UdpTransport {
+	fn send(&mut self, frame: &[u8]) -> io::Result<()> {
+		let (count, fragments) = fragments(frame)?;
+		let now = Instant::now();
+
+		for (index, data) in fragments.enumerate() {
+			let sequence = self.next_reliable;
+			let datagram = Fragment::encode(RELIABLE, sequence, index as u16, count, data);
+
+			self.next_reliable += 1;
+			self.send_datagram(&datagram)?;
+			self.unacked.insert(sequence, (now, 0, datagram));
+		}
+
+		if self.unacked.len() > MAX_UNACKED {
+			return Err(io::Error::other(format!(
+				"{} unacknowledged fragments exceed the maximum",
+				self.unacked.len()
+			)));
+		}
+
+		Ok(())
+	}
+
+	fn send_unreliable(&mut self, frame: &[u8]) -> io::Result<()> {
+		let (count, fragments) = fragments(frame)?;
+		let sequence = self.next_unreliable;
+
+		self.next_unreliable += 1;
+
+		for (index, data) in fragments.enumerate() {
+			self.send_datagram(&Fragment::encode(
+				UNRELIABLE,
+				sequence,
+				index as u16,
+				count,
+				data,
+			))?;
+		}
+
+		Ok(())
+	}
+
+	fn recv(&mut self) -> io::Result<Vec<u8>> {
+		self.resend()?;
+
+		loop {
+			if let Some(message) = self.ready.pop_front() {
+				return Ok(message);
+			}
+
+			if self.closed {
+				return Err(io::ErrorKind::ConnectionAborted.into());
+			}
+
+			let datagram = self.inbound.recv()?;
+
+			self.handle(&datagram)?;
+		}
+	}
+
+	fn set_max_frame_size(&mut self, max: usize) {
+		self.max_frame_size = max;
+	}
+
+	fn peer_addr(&self) -> Option<SocketAddr> {
+		Some(self.peer)
+	}
+}
+
+impl Drop for UdpTransport {
+	fn drop(&mut self) {
+		if !self.closed {
+			let _ = self.send_datagram(&[DISCONNECT]);
+		}
+	}
+}
+
+/// Accepts connections over UDP.
+///
+/// A background thread reads every datagram sent to the socket and forwards it
+/// to the [`UdpTransport`] of its sender, so all connections share a single port.
+///
+/// # Examples
+///
+/// ```rust
+/// use std::io;
+///
+/// use ira::transport::{Listener, Transport, UdpListener, UdpTransport};
+///
+/// fn recv(transport: &mut dyn Transport) -> Vec<u8> {
+///     loop {
+///         match transport.recv() {
+///             Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+///             frame => return frame.unwrap(),
+///         }
+///     }
+/// }
+///
+/// let mut listener = UdpListener::bind("127.0.0.1:0").unwrap();
+/// let mut client = UdpTransport::connect(listener.local_addr().unwrap()).unwrap();
+/// let mut server = listener.accept().unwrap();
+///
+/// // large frames are split into multiple datagrams
+/// let frame = (0..10_000).map(|i| i as u8).collect::<Vec<_>>();
+///
+/// client.send(&frame).unwrap();
+/// client.send_unreliable(b"position").unwrap();
+///
+/// let mut received = vec![recv(&mut *server), recv(&mut *server)];
+/// received.sort_by_key(Vec::len);
+///
+/// assert_eq!(received, [b"position".to_vec(), frame]);
+/// ```
+pub struct UdpListener {
+	local_addr: SocketAddr,
+	connections: mpsc::Receiver<UdpTransport>,
+}
+
+impl UdpListener {
+	/// Binds a new listener to the given address.
+	///
+	/// # Errors
+	///
+	/// See [`UdpSocket::bind`].
+	pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+		let socket = Arc::new(UdpSocket::bind(addr)?);
+		let local_addr = socket.local_addr()?;
+		let (tx, connections) = mpsc::channel();
+
+		std::thread::spawn(move || Self::demultiplex(&socket, &tx));
+
+		Ok(Self {
+			local_addr,
+			connections,
+		})
+	}
+
+	fn demultiplex(socket: &Arc<UdpSocket>, connections: &mpsc::Sender<UdpTransport>) {
+		let mut peers: HashMap<SocketAddr, mpsc::SyncSender<Vec<u8>>> = HashMap::new();
+		let mut buf = vec![0; MTU];
+
+		loop {
+			let (n, peer) = match socket.recv_from(&mut buf) {
+				Ok(received) => received,
+				Err(e) => {
+					warn!(error = ?e, "failed to receive datagram");
+					continue;
+				}
+			};
+
+			let datagram = &buf[..n];
+
+			match peers.entry(peer) {
+				Entry::Occupied(entry) => {
+					if datagram.first() == Some(&CONNECT) {
+						// the previous reply may have been lost
+						let _ = socket.send_to(&[CONNECT], peer);
+						continue;
+					}
+
+					let disconnect = datagram.first() == Some(&DISCONNECT);
+
+					let closed = match entry.get().try_send(datagram.to_vec()) {
+						Ok(()) => false,
+						// the connection is not keeping up, so drop the datagram
+						Err(TrySendError::Full(..)) => {
+							debug!(%peer, "dropped datagram");
+							false
+						}
+						Err(TrySendError::Disconnected(..)) => true,
+					};
+
+					if closed || disconnect {
+						debug!(%peer, "connection closed");
+						entry.remove();
+					}
+				}
+				Entry::Vacant(entry) => {
+					if datagram.first() != Some(&CONNECT) {
+						continue;
+					}
+
+					info!(%peer, "accepted connection");
+
+					let (tx, rx) = mpsc::sync_channel(RECV_QUEUE_LEN);
+
+					if socket.send_to(&[CONNECT], peer).is_err() {
+						continue;
+					}
+
+					let transport =
+						UdpTransport::new(Arc::clone(socket), peer, Inbound::Channel(rx));
+
+					if connections.send(transport).is_err() {
+						// the listener has been dropped
+						return;
+					}
+
+					entry.insert(tx);
+				}
+			}
+		}
+	}
+}
+
+impl Listener for UdpListener {
+	fn accept(&mut self) -> io::Result<Box<dyn Transport>> {
+		match self.connections.recv() {
+			Ok(transport) => Ok(Box::new(transport)),
+			Err(_) => Err(io::ErrorKind::BrokenPipe.into()),
+		}
+	}
+
+	fn local_addr(&self) -> Option<SocketAddr> {
+		Some(self.local_addr)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Connects a client to a server, which only acknowledges fragments while reading.
+	fn connect() -> (UdpTransport, Box<dyn Transport>) {
+		let mut listener = UdpListener::bind("127.0.0.1:0").unwrap();
+		let client = UdpTransport::connect(listener.local_addr().unwrap()).unwrap();
+		let server = listener.accept().unwrap();
+
+		(client, server)
+	}
+
+	/// Encodes a frame into reliable fragments, starting at `sequence`.
+	fn reliable(sequence: u32, frame: &[u8]) -> Vec<Vec<u8>> {
+		let (count, fragments) = fragments(frame).unwrap();
+
+		fragments
+			.enumerate()
+			.map(|(index, data)| {
+				Fragment::encode(RELIABLE, sequence + index as u32, index as u16, count, data)
+			})
+			.collect()
+	}
+
+	#[test]
+	fn reliable_frames_are_delivered_in_order() {
+		let (mut client, _server) = connect();
+		let first = (0..FRAGMENT_LEN * 3).map(|i| i as u8).collect::<Vec<_>>();
+
+		let mut datagrams = reliable(0, &first);
+		datagrams.extend(reliable(3, b"second"));
+
+		// lost and resent fragments arrive out of order and more than once
+		for index in [3, 1, 1, 0, 3, 2, 0] {
+			client.handle(&datagrams[index]).unwrap();
+		}
+
+		assert_eq!(client.recv().unwrap(), first);
+		assert_eq!(client.recv().unwrap(), b"second");
+		assert_eq!(client.recv().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+	}
+
+	#[test]
+	fn acknowledged_fragments_are_not_resent() {
+		let (mut client, mut server) = connect();
+		let frame = vec![7; FRAGMENT_LEN * 2];
+
+		client.send(&frame).unwrap();
+		assert_eq!(client.unacked.len(), 2);
+
+		let received = loop {
+			match server.recv() {
+				Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+				frame => break frame.unwrap(),
+			}
+		};
+
+		assert_eq!(received, frame);
+
+		while !client.unacked.is_empty() {
+			assert_eq!(client.recv().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+			std::thread::yield_now();
+		}
+	}
+
+	#[test]
+	fn connection_is_closed_when_too_many_fragments_are_unacknowledged() {
+		let (mut client, _server) = connect();
+		let frame = vec![0; FRAGMENT_LEN * 1_000];
+
+		let closed = (0..MAX_UNACKED / 1_000 + 2).any(|_| client.send(&frame).is_err());
+
+		assert!(closed);
+	}
+
+	#[test]
+	fn connection_is_closed_after_too_many_resends() {
+		let (mut client, _server) = connect();
+
+		client.send(b"reliable").unwrap();
+
+		for (_, resends, _) in client.unacked.values_mut() {
+			*resends = MAX_RESENDS;
+		}
+
+		std::thread::sleep(RESEND_TIMEOUT);
+
+		let error = client.recv().unwrap_err();
+
+		assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+	}
+}
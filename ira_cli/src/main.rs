@@ -1,7 +1,7 @@
 use std::path::PathBuf;
 
-use clap::{Parser, ValueHint};
-use ira_drum::{Drum, DrumBuilder, Mipmaps};
+use clap::{Parser, ValueEnum, ValueHint};
+use ira_drum::{CollisionKind, Drum, DrumBuilder, Mipmaps};
 use tracing::{info, warn};
 
 #[derive(Parser)]
@@ -33,6 +33,10 @@ enum Args {
 		/// mipmaps will be generated automatically.
 		#[arg(short, long)]
 		mipmaps: Option<Option<u32>>,
+		/// The collision shape to bake into each model. If not specified,
+		/// no collision shapes will be baked.
+		#[arg(short = 'k', long)]
+		collision: Option<Collision>,
 	},
 	/// Displays information about a Drum.
 	Info {
@@ -42,6 +46,26 @@ enum Args {
 	},
 }
 
+#[derive(Clone, Copy, ValueEnum)]
+enum Collision {
+	/// A single convex hull wrapping the model.
+	ConvexHull,
+	/// A triangle mesh matching the model exactly.
+	Trimesh,
+	/// A set of convex parts approximating the model.
+	ConvexDecomposition,
+}
+
+impl From<Collision> for CollisionKind {
+	fn from(collision: Collision) -> Self {
+		match collision {
+			Collision::ConvexHull => Self::ConvexHull,
+			Collision::Trimesh => Self::TriMesh,
+			Collision::ConvexDecomposition => Self::ConvexDecomposition,
+		}
+	}
+}
+
 fn main() -> anyhow::Result<()> {
 	let args = Args::parse();
 
@@ -57,8 +81,9 @@ fn main() -> anyhow::Result<()> {
 			brdf,
 			output,
 			srgb,
+			collision,
 		} => {
-			let drum = pack(
+			let mut drum = pack(
 				assets,
 				compress,
 				srgb,
@@ -68,6 +93,14 @@ fn main() -> anyhow::Result<()> {
 				brdf,
 			)?;
 
+			if let Some(collision) = collision {
+				let kind = CollisionKind::from(collision);
+
+				info!(?kind, "baking collision shapes");
+
+				drum.bake_collision(kind);
+			}
+
 			drum.write_to_path(output)?;
 		}
 		Args::Info { drum } => {
@@ -0,0 +1,111 @@
+use std::fmt;
+
+use bincode::{Decode, Encode};
+
+use crate::Vec3;
+#[cfg(feature = "collision")]
+use crate::{Mesh, MeshHandles};
+
+/// Collision shapes computed ahead of time for a model.
+///
+/// All points are in the model's local space, before any instance scale is applied.
+#[derive(Debug, Encode, Decode)]
+pub enum Collision {
+	/// A convex hull wrapping all meshes of the model.
+	ConvexHull { points: Box<[Vec3]> },
+	/// A triangle mesh matching all meshes of the model.
+	TriMesh {
+		vertices: Box<[Vec3]>,
+		indices: Box<[[u32; 3]]>,
+	},
+	/// A set of convex parts, each given by the points of its hull.
+	Compound { parts: Box<[Box<[Vec3]>]> },
+}
+
+/// The kind of collision shape to compute for a model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CollisionKind {
+	ConvexHull,
+	TriMesh,
+	ConvexDecomposition,
+}
+
+impl fmt::Display for Collision {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::ConvexHull { points } => write!(f, "convex hull, {} points", points.len()),
+			Self::TriMesh { indices, .. } => write!(f, "trimesh, {} triangles", indices.len()),
+			Self::Compound { parts } => write!(f, "compound, {} parts", parts.len()),
+		}
+	}
+}
+
+impl Collision {
+	/// Returns the vertices and triangles of all meshes referenced by `handles`.
+	#[cfg(feature = "collision")]
+	fn triangles(meshes: &[Mesh], handles: &MeshHandles) -> (Vec<Vec3>, Vec<[u32; 3]>) {
+		let mut vertices = Vec::new();
+		let mut indices = Vec::new();
+
+		for mesh in handles.opaque.iter().chain(handles.transparent.iter()) {
+			let mesh = mesh.resolve(meshes);
+			let offset = vertices.len() as u32;
+
+			vertices.extend(mesh.vertices.iter().map(|v| v.position));
+			indices.extend(
+				mesh.indices
+					.chunks_exact(3)
+					.map(|t| [t[0] + offset, t[1] + offset, t[2] + offset]),
+			);
+		}
+
+		(vertices, indices)
+	}
+
+	/// Computes the collision shape of a model from its meshes.
+	#[must_use]
+	#[cfg(feature = "collision")]
+	#[tracing::instrument(skip(meshes, handles))]
+	pub fn from_meshes(meshes: &[Mesh], handles: &MeshHandles, kind: CollisionKind) -> Self {
+		use parry3d::{
+			math::Point,
+			transformation::{
+				self,
+				vhacd::{VHACDParameters, VHACD},
+			},
+		};
+
+		let (vertices, indices) = Self::triangles(meshes, handles);
+
+		let to_point = |v: &Vec3| Point::new(v.x, v.y, v.z);
+		let from_point = |p: &Point<f32>| Vec3::new(p.x, p.y, p.z);
+
+		match kind {
+			CollisionKind::TriMesh => Self::TriMesh {
+				vertices: vertices.into_boxed_slice(),
+				indices: indices.into_boxed_slice(),
+			},
+			CollisionKind::ConvexHull => {
+				let points = vertices.iter().map(to_point).collect::<Vec<_>>();
+				let (hull, _) = transformation::convex_hull(&points);
+
+				Self::ConvexHull {
+					points: hull.iter().map(from_point).collect(),
+				}
+			}
+			CollisionKind::ConvexDecomposition => {
+				let points = vertices.iter().map(to_point).collect::<Vec<_>>();
+				let decomposition =
+					VHACD::decompose(&VHACDParameters::default(), &points, &indices, true);
+
+				Self::Compound {
+					parts: decomposition
+						.compute_exact_convex_hulls(&points, &indices)
+						.iter()
+						.map(|(hull, _)| hull.iter().map(from_point).collect())
+						.collect(),
+				}
+			}
+		}
+	}
+}
@@ -1,4 +1,4 @@
-use std::{fmt, fs::File, io, path::Path};
+use std::{fmt, fs::File, hash::Hasher, io, path::Path};
 
 use bincode::{
 	error::{DecodeError, EncodeError},
@@ -12,6 +12,18 @@ use crate::{source::Source, Extent3d, Handle};
 
 pub const CONFIG: bincode::config::Configuration = bincode::config::standard();
 
+/// Written before the compressed data of a drum, followed by its format version.
+///
+/// The first byte is a reserved deflate block type, so drums written before the
+/// header was added, which start with deflate data, can never match it.
+const MAGIC: [u8; 4] = [0xff, b'D', b'R', b'M'];
+
+/// The current version of the drum format.
+///
+/// - Version 0 is the headerless format, where models have no collision shape.
+/// - Version 1 adds [`super::Model::collision`].
+pub const VERSION: u32 = 1;
+
 #[derive(Debug, Encode, Decode)]
 pub struct Drum {
 	pub textures: Box<[super::Texture]>,
@@ -25,6 +37,47 @@ pub struct Drum {
 	pub prefiltered_map: Option<super::Texture>,
 }
 
+/// The layout of a version 0 drum, whose models have no collision shape.
+#[derive(Decode)]
+#[cfg_attr(test, derive(Encode))]
+struct DrumV0 {
+	textures: Box<[super::Texture]>,
+	materials: Box<[super::Material]>,
+	meshes: Box<[super::Mesh]>,
+	models: Box<[super::model::ModelV0]>,
+	lights: Box<[super::Light]>,
+
+	brdf_lut: Option<super::Texture>,
+	irradiance_map: Option<super::Texture>,
+	prefiltered_map: Option<super::Texture>,
+}
+
+impl From<DrumV0> for Drum {
+	fn from(drum: DrumV0) -> Self {
+		Self {
+			textures: drum.textures,
+			materials: drum.materials,
+			meshes: drum.meshes,
+			models: drum.models.into_vec().into_iter().map(Into::into).collect(),
+			lights: drum.lights,
+			brdf_lut: drum.brdf_lut,
+			irradiance_map: drum.irradiance_map,
+			prefiltered_map: drum.prefiltered_map,
+		}
+	}
+}
+
+/// Feeds encoded data into a hasher.
+struct HashWriter<'h, H>(&'h mut H);
+
+impl<H: Hasher> bincode::enc::write::Writer for HashWriter<'_, H> {
+	fn write(&mut self, bytes: &[u8]) -> Result<(), EncodeError> {
+		self.0.write(bytes);
+
+		Ok(())
+	}
+}
+
 impl fmt::Display for Drum {
 	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
 		writeln!(f, "Texture count: {}", self.textures.len())?;
@@ -33,7 +86,10 @@ impl fmt::Display for Drum {
 		writeln!(f, "Model count: {}", self.models.len())?;
 
 		for (i, model) in self.models.iter().enumerate() {
-			writeln!(f, "  {i}: {}", model.name)?;
+			match &model.collision {
+				Some(collision) => writeln!(f, "  {i}: {} ({collision})", model.name)?,
+				None => writeln!(f, "  {i}: {}", model.name)?,
+			}
 		}
 
 		writeln!(f, "Light count: {}", self.lights.len())?;
@@ -49,15 +105,38 @@ impl Drum {
 	/// When reading and writing with paths, the buffer size is 1MB.
 	pub const BUF_SIZE: usize = 1_024 * 1_024;
 
-	/// Reads a drum from a reader.
+	/// Reads a drum from a reader. Drums written with an older version of the format
+	/// are still supported.
 	///
 	/// # Errors
 	///
+	/// Returns an error if the drum was written with a newer version of the format.
 	/// See [`bincode::decode_from_std_read`] for more information.
-	pub fn from_reader<R: io::BufRead>(reader: R) -> Result<Self, DecodeError> {
+	pub fn from_reader<R: io::BufRead>(mut reader: R) -> Result<Self, DecodeError> {
+		let io_error = |inner| DecodeError::Io {
+			inner,
+			additional: 0,
+		};
+
+		let version = if reader.fill_buf().map_err(io_error)?.starts_with(&MAGIC) {
+			let mut header = [0; MAGIC.len() + 4];
+
+			reader.read_exact(&mut header).map_err(io_error)?;
+
+			u32::from_le_bytes([header[4], header[5], header[6], header[7]])
+		} else {
+			0
+		};
+
 		let mut decoder = DeflateDecoder::new(reader);
 
-		bincode::decode_from_std_read(&mut decoder, CONFIG)
+		match version {
+			0 => {
+				bincode::decode_from_std_read::<DrumV0, _, _>(&mut decoder, CONFIG).map(Into::into)
+			}
+			VERSION => bincode::decode_from_std_read(&mut decoder, CONFIG),
+			_ => Err(DecodeError::Other("unsupported drum version")),
+		}
 	}
 
 	/// Reads a drum from a file.
@@ -77,12 +156,34 @@ impl Drum {
 		Self::from_reader(file)
 	}
 
+	/// Feeds the contents of the drum into a hasher, so that two drums hash the same
+	/// only if they are interchangeable for the simulation.
+	///
+	/// Texture data is skipped since it only affects rendering, and would make
+	/// hashing large drums slow. Only the number of textures is included, so the
+	/// texture handles of materials still refer to the same textures.
+	pub fn hash_content<H: Hasher>(&self, hasher: &mut H) {
+		hasher.write(&(self.textures.len() as u64).to_le_bytes());
+
+		// encoding into a hasher cannot fail
+		let _ = bincode::encode_into_writer(
+			(&self.materials, &self.meshes, &self.models, &self.lights),
+			HashWriter(hasher),
+			CONFIG,
+		);
+	}
+
 	/// Writes the drum to a writer.
 	///
 	/// # Errors
 	///
 	/// See [`bincode::encode_into_std_write`] for more information.
 	pub fn write<W: io::Write>(&self, writer: &mut W) -> Result<usize, EncodeError> {
+		writer
+			.write_all(&MAGIC)
+			.and_then(|()| writer.write_all(&VERSION.to_le_bytes()))
+			.map_err(|inner| EncodeError::Io { inner, index: 0 })?;
+
 		let mut encoder = DeflateEncoder::new(writer, Compression::default());
 
 		bincode::encode_into_std_write(self, &mut encoder, CONFIG)
@@ -157,6 +258,20 @@ impl Drum {
 		Ok(())
 	}
 
+	/// Computes and stores a collision shape for every model in the drum,
+	/// replacing any previously baked shapes.
+	#[cfg(feature = "collision")]
+	#[tracing::instrument(skip(self))]
+	pub fn bake_collision(&mut self, kind: super::CollisionKind) {
+		for model in &mut self.models {
+			model.collision = Some(super::Collision::from_meshes(
+				&self.meshes,
+				&model.meshes,
+				kind,
+			));
+		}
+	}
+
 	/// Turns the drum into a builder.
 	#[must_use]
 	pub fn into_builder(self) -> DrumBuilder {
@@ -256,3 +371,98 @@ impl DrumBuilder {
 		source.add_to_drum(self)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{model::ModelV0, MeshHandles, Vec3};
+
+	fn meshes() -> MeshHandles {
+		MeshHandles {
+			opaque: Box::new([]),
+			transparent: Box::new([]),
+		}
+	}
+
+	fn drum(center: Vec3) -> Drum {
+		Drum {
+			textures: Box::new([]),
+			materials: Box::new([]),
+			meshes: Box::new([]),
+			models: Box::new([crate::Model {
+				name: "cube".into(),
+				meshes: meshes(),
+				center,
+				collision: None,
+			}]),
+			lights: Box::new([]),
+			brdf_lut: None,
+			irradiance_map: None,
+			prefiltered_map: None,
+		}
+	}
+
+	fn hash(drum: &Drum) -> u64 {
+		let mut hasher = std::hash::DefaultHasher::new();
+
+		drum.hash_content(&mut hasher);
+		hasher.finish()
+	}
+
+	#[test]
+	fn content_changes_the_hash() {
+		let hashed = hash(&drum(Vec3::new(1.0, 2.0, 3.0)));
+
+		assert_eq!(hashed, hash(&drum(Vec3::new(1.0, 2.0, 3.0))));
+		assert_ne!(hashed, hash(&drum(Vec3::new(1.0, 2.0, 4.0))));
+	}
+
+	#[test]
+	fn current_version_round_trips() {
+		let drum = drum(Vec3::new(1.0, 2.0, 3.0));
+
+		let bytes = drum.to_vec().unwrap();
+		let decoded = Drum::from_reader(bytes.as_slice()).unwrap();
+
+		assert_eq!(&*decoded.models[0].name, "cube");
+	}
+
+	#[test]
+	fn version_0_is_decoded() {
+		let legacy = DrumV0 {
+			textures: Box::new([]),
+			materials: Box::new([]),
+			meshes: Box::new([]),
+			models: Box::new([ModelV0 {
+				name: "cube".into(),
+				meshes: meshes(),
+				center: Vec3::new(1.0, 2.0, 3.0),
+			}]),
+			lights: Box::new([]),
+			brdf_lut: None,
+			irradiance_map: None,
+			prefiltered_map: None,
+		};
+
+		// version 0 drums are only the compressed data, without a header
+		let mut bytes = Vec::new();
+		let mut encoder = DeflateEncoder::new(&mut bytes, Compression::default());
+
+		bincode::encode_into_std_write(legacy, &mut encoder, CONFIG).unwrap();
+		encoder.finish().unwrap();
+
+		let decoded = Drum::from_reader(bytes.as_slice()).unwrap();
+
+		assert_eq!(&*decoded.models[0].name, "cube");
+		assert!(decoded.models[0].collision.is_none());
+	}
+
+	#[test]
+	fn newer_version_is_rejected() {
+		let mut bytes = MAGIC.to_vec();
+
+		bytes.extend_from_slice(&(VERSION + 1).to_le_bytes());
+
+		assert!(Drum::from_reader(bytes.as_slice()).is_err());
+	}
+}
@@ -15,6 +15,7 @@
 //! - Tangents
 //! - Bitangents
 //! - Material index
+//! - Collision shape (convex hull, triangle mesh or convex decomposition)
 
 #![warn(clippy::pedantic)]
 #![allow(clippy::module_name_repetitions)]
@@ -32,6 +33,7 @@ pub use tobj;
 
 pub use image_dds::Mipmaps;
 
+pub mod collision;
 pub mod drum;
 pub mod handle;
 pub mod light;
@@ -39,6 +41,7 @@ pub mod material;
 pub mod model;
 pub mod source;
 
+pub use collision::*;
 pub use drum::*;
 pub use handle::*;
 pub use light::*;
@@ -3,7 +3,7 @@ use std::{fmt, ops};
 use bincode::{Decode, Encode};
 use bytemuck::{Pod, Zeroable};
 
-use crate::{handle::Handle, material::Material};
+use crate::{collision::Collision, handle::Handle, material::Material};
 
 #[must_use]
 #[repr(C)]
@@ -347,4 +347,28 @@ pub struct Model {
 
 	pub meshes: MeshHandles,
 	pub center: Vec3,
+
+	/// The collision shape baked at pack time, if any.
+	pub collision: Option<Collision>,
+}
+
+/// The layout of a model in a version 0 drum, which has no collision shape.
+#[derive(Decode)]
+#[cfg_attr(test, derive(Encode))]
+pub(crate) struct ModelV0 {
+	pub name: Box<str>,
+
+	pub meshes: MeshHandles,
+	pub center: Vec3,
+}
+
+impl From<ModelV0> for Model {
+	fn from(model: ModelV0) -> Self {
+		Self {
+			name: model.name,
+			meshes: model.meshes,
+			center: model.center,
+			collision: None,
+		}
+	}
 }
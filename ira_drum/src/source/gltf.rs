@@ -155,6 +155,7 @@ impl Source for GltfSource {
 				transparent: transparent_meshes.into_boxed_slice(),
 			},
 			center: centroid,
+			collision: None,
 		};
 
 		drum.add_model(model);
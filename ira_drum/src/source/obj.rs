@@ -181,6 +181,7 @@ impl Source for ObjSource {
 				transparent: transparent_meshes.into_boxed_slice(),
 			},
 			center: centroid,
+			collision: None,
 		};
 
 		drum.add_model(model);